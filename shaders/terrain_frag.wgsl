@@ -17,12 +17,82 @@ var river_map: texture_2d<f32>;
 @group(0) @binding(3)
 var river_sampler: sampler;
 
+// Ore, oil, fertile soil and timber, one per channel
 @group(0) @binding(4)
 var resource_map: texture_2d<f32>;
 
 @group(0) @binding(5)
 var resource_sampler: sampler;
 
+@group(0) @binding(6)
+var lake_map: texture_2d<f32>;
+
+@group(0) @binding(7)
+var lake_sampler: sampler;
+
+@group(0) @binding(8)
+var ocean_mask: texture_2d<f32>;
+
+@group(0) @binding(9)
+var ocean_sampler: sampler;
+
+// Binding 10 holds the normal map, which only the vertex shader reads
+
+@group(0) @binding(12)
+var biome_map: texture_2d<f32>;
+
+// Stone and fresh water in the first two channels
+@group(0) @binding(14)
+var resource_map_2: texture_2d<f32>;
+
+// Indexed by the discriminants of `Biome` in biome.rs
+var<private> BIOME_COLORS: array<vec3f, 8> = array<vec3f, 8>(
+    vec3f(0.45, 0.70, 0.30), // grassland
+    vec3f(0.13, 0.42, 0.18), // forest
+    vec3f(0.86, 0.76, 0.50), // desert
+    vec3f(0.80, 0.84, 0.86), // tundra
+    vec3f(0.32, 0.45, 0.33), // wetland
+    vec3f(0.93, 0.87, 0.62), // beach
+    vec3f(0.50, 0.47, 0.45), // mountain
+    vec3f(0.00, 0.00, 1.00), // water
+);
+
+// Opacity of the resource overlay where a resource is at its richest. The overlay is turned off by
+// binding empty resource maps, see `resources.overlay` in world.toml
+const RESOURCE_OVERLAY_OPACITY: f32 = 0.8;
+
+// One colour per kind of resource, in the order of `ResourceKind::ALL` in world_config.rs
+var<private> RESOURCE_COLORS: array<vec3f, 6> = array<vec3f, 6>(
+    vec3f(0.85, 0.35, 0.10), // ore
+    vec3f(0.10, 0.08, 0.08), // oil
+    vec3f(0.45, 0.30, 0.15), // fertile soil
+    vec3f(0.05, 0.30, 0.05), // timber
+    vec3f(0.65, 0.65, 0.70), // stone
+    vec3f(0.30, 0.80, 0.95), // fresh water
+);
+
+// Blends the colours of the resources in a cell over `color`, weighted by their amounts
+fn resource_overlay(color: vec3f, tex_coords: vec2f) -> vec3f {
+    // The resource maps have one pixel per heightmap cell, including the border cell
+    let resource_coords = (tex_coords + vec2f(1.5, 1.5)) / vec2f(textureDimensions(resource_map));
+    let first = textureSample(resource_map, resource_sampler, resource_coords);
+    let second = textureSample(resource_map_2, resource_sampler, resource_coords);
+    var amounts = array<f32, 6>(first.x, first.y, first.z, first.w, second.x, second.y);
+
+    var resource_color = vec3f(0.0);
+    var total = 0.0;
+    var strongest = 0.0;
+    for (var i = 0; i < 6; i++) {
+        resource_color += RESOURCE_COLORS[i] * amounts[i];
+        total += amounts[i];
+        strongest = max(strongest, amounts[i]);
+    }
+    if total <= 0.0 {
+        return color;
+    }
+    return mix(color, resource_color / total, strongest * RESOURCE_OVERLAY_OPACITY);
+}
+
 struct Camera {
     view_pos: vec4<f32>,
     view_proj: mat4x4<f32>,
@@ -33,12 +103,18 @@ var<uniform> camera: Camera;
 
 @fragment
 fn main(in: VertexOutput) -> @location(0) vec4<f32> {
-    return textureSample(resource_map, resource_sampler, in.tex_coords / (f32(textureDimensions(height_map).x) - 2.0));
-    /* let river_val = textureSample(river_map, river_sampler, in.tex_coords / (f32(textureDimensions(height_map).x) - 2.0)).x;
+    let map_coords = in.tex_coords / (f32(textureDimensions(height_map).x) - 2.0);
+    let river_val = textureSample(river_map, river_sampler, map_coords).x;
+    let lake_level = textureSample(lake_map, lake_sampler, map_coords).x;
+    let terrain_height = textureSample(height_map, height_sampler, map_coords).x;
+    let ocean_val = textureSample(ocean_mask, ocean_sampler, map_coords).x;
+    let water_val = max(max(river_val, ocean_val), f32(lake_level > terrain_height));
+    let biome = u32(round(textureLoad(biome_map, vec2i(in.tex_coords + vec2f(1.0, 1.0)), 0).x));
     let light_position = vec3f(-5.0, 5.0, 7.0);
 
     let highlight_color = vec3f(1.0);
-    let surface_color = mix(vec3f(0.0, 1.0, 0.0), vec3f(0.0, 0.0, 1.0), river_val);
+    var surface_color = mix(BIOME_COLORS[min(biome, 7u)], BIOME_COLORS[7], water_val);
+    surface_color = resource_overlay(surface_color, in.tex_coords);
     let cool_color = vec3f(0.0, 0.0, 0.55) + 0.25 * surface_color;
     let warm_color = vec3f(0.3, 0.3, 0.0) + 0.25 * surface_color;
 
@@ -49,5 +125,5 @@ fn main(in: VertexOutput) -> @location(0) vec4<f32> {
     let r = 2.0 * light_contribution * in.normal - vector_to_light;
     let s = clamp(100.0 * dot(r,vector_to_camera) - 97.0, 0.0, 1.0);
 
-    return vec4f(mix(highlight_color, mix(warm_color, cool_color, 1.0 - t), 1.0 - s), 1.0); */
+    return vec4f(mix(highlight_color, mix(warm_color, cool_color, 1.0 - t), 1.0 - s), 1.0);
 }
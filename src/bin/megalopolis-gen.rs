@@ -0,0 +1,356 @@
+use std::{fs, path::Path, path::PathBuf};
+
+use clap::Parser;
+use megalopolis::{
+    biome::{self, Biome, BiomeMap},
+    erosion,
+    heightmap::Heightmap,
+    hydrology::{self, FlowMap},
+    lake_generator::LakeMap,
+    noise,
+    ocean::{self, OceanMap},
+    resource_generator::ResourceMap,
+    river_generator, river_network,
+    terrain_normals::TerrainNormals,
+    world_config::{ResourceKind, WorldConfig},
+};
+use serde::Serialize;
+
+/// Generates a world without opening a window and writes every map layer to disk as a 16-bit
+/// grayscale PNG and a raw little-endian f32 file, along with a JSON manifest. Parameters are read
+/// from the world config, and any flag given here overrides the config value.
+#[derive(Parser, Debug)]
+#[command(name = "megalopolis-gen")]
+struct Args {
+    /// World config to start from. The default world is used if the file does not exist
+    #[arg(short, long, default_value = "world.toml")]
+    config: PathBuf,
+
+    /// Directory the layers and manifest are written to
+    #[arg(short, long, default_value = "world")]
+    output: PathBuf,
+
+    #[arg(long)]
+    seed: Option<u64>,
+
+    /// Side length of the terrain in world units
+    #[arg(long)]
+    terrain_size: Option<f32>,
+
+    /// Number of quads along each side of the terrain mesh
+    #[arg(long)]
+    terrain_resolution: Option<usize>,
+
+    /// Number of noise grid cells along each side of the terrain
+    #[arg(long)]
+    perlin_size: Option<usize>,
+
+    #[arg(long)]
+    perlin_octaves: Option<usize>,
+
+    #[arg(long)]
+    perlin_persistence: Option<f32>,
+
+    /// Period after which Perlin noise repeats, in noise grid cells. Zero disables tiling
+    #[arg(long)]
+    perlin_tile_period: Option<u32>,
+
+    /// Number of hydraulic erosion droplets. Zero disables the pass
+    #[arg(long)]
+    erosion_droplets: Option<usize>,
+
+    /// Number of thermal erosion passes. Zero disables the pass
+    #[arg(long)]
+    thermal_iterations: Option<usize>,
+
+    /// Height below which cells connected to the map edge are ocean. Zero disables the ocean
+    #[arg(long)]
+    sea_level: Option<f32>,
+
+    #[arg(long)]
+    river_count: Option<usize>,
+
+    /// Radius around the river curve, in world units
+    #[arg(long)]
+    river_size: Option<f32>,
+
+    #[arg(long)]
+    river_shift_iterations: Option<usize>,
+
+    /// Number of tributaries joining each river
+    #[arg(long)]
+    river_tributary_count: Option<usize>,
+
+    /// The resource settings apply to every kind of resource
+    #[arg(long)]
+    resource_splat_count: Option<usize>,
+
+    #[arg(long)]
+    resource_splat_spread: Option<f32>,
+
+    #[arg(long)]
+    resource_spread_between_splats: Option<f32>,
+
+    #[arg(long)]
+    resource_points_per_splat: Option<usize>,
+
+    #[arg(long)]
+    resource_magnitude: Option<f32>,
+
+    #[arg(long)]
+    resource_spread: Option<f32>,
+}
+
+impl Args {
+    fn apply_overrides(&self, config: &mut WorldConfig) {
+        fn set<T: Copy>(target: &mut T, value: Option<T>) {
+            if let Some(value) = value {
+                *target = value;
+            }
+        }
+
+        set(&mut config.seed, self.seed);
+        set(&mut config.terrain.size, self.terrain_size);
+        set(&mut config.terrain.resolution, self.terrain_resolution);
+        set(&mut config.terrain.perlin_size, self.perlin_size);
+        set(&mut config.terrain.perlin_octaves, self.perlin_octaves);
+        set(
+            &mut config.terrain.perlin_persistence,
+            self.perlin_persistence,
+        );
+        set(
+            &mut config.terrain.perlin_tile_period,
+            self.perlin_tile_period,
+        );
+        set(
+            &mut config.hydraulic_erosion.droplet_count,
+            self.erosion_droplets,
+        );
+        set(
+            &mut config.thermal_erosion.iterations,
+            self.thermal_iterations,
+        );
+        set(&mut config.ocean.sea_level, self.sea_level);
+        set(&mut config.river.count, self.river_count);
+        set(&mut config.river.size, self.river_size);
+        set(
+            &mut config.river.shift_iterations,
+            self.river_shift_iterations,
+        );
+        set(
+            &mut config.river.tributary_count,
+            self.river_tributary_count,
+        );
+        for kind in ResourceKind::ALL {
+            let resources = config.resources.get_mut(kind);
+            set(&mut resources.splat_count, self.resource_splat_count);
+            set(&mut resources.splat_spread, self.resource_splat_spread);
+            set(
+                &mut resources.spread_between_splats,
+                self.resource_spread_between_splats,
+            );
+            set(
+                &mut resources.points_per_splat,
+                self.resource_points_per_splat,
+            );
+            set(&mut resources.magnitude, self.resource_magnitude);
+            set(&mut resources.spread, self.resource_spread);
+        }
+    }
+}
+
+#[derive(Debug, Serialize)]
+struct LayerManifest {
+    name: String,
+    png: String,
+    raw: String,
+    width: u32,
+    height: u32,
+}
+
+#[derive(Debug, Serialize)]
+struct Manifest<'a> {
+    config: &'a WorldConfig,
+    layers: Vec<LayerManifest>,
+}
+
+fn main() {
+    if let Err(err) = run(Args::parse()) {
+        eprintln!("megalopolis-gen: {err}");
+        std::process::exit(1);
+    }
+}
+
+fn run(args: Args) -> Result<(), Box<dyn std::error::Error>> {
+    let mut config = WorldConfig::load_or_default(&args.config)?;
+    args.apply_overrides(&mut config);
+    config.validate()?;
+
+    fs::create_dir_all(&args.output)?;
+
+    let terrain_noise = noise::from_config(&config.terrain, config.seed);
+    let mut terrain_height_map = Heightmap::from_noise(
+        &terrain_noise,
+        config.terrain.perlin_size,
+        config.terrain.resolution,
+        config.terrain.size,
+    );
+    ocean::apply_falloff(&mut terrain_height_map, &config.ocean, config.seed);
+    erosion::hydraulic_erosion(
+        &mut terrain_height_map,
+        &config.hydraulic_erosion,
+        config.seed,
+    );
+    erosion::thermal_erosion(&mut terrain_height_map, &config.thermal_erosion);
+    let texture_res = terrain_height_map.resolution();
+
+    let ocean = OceanMap::new(&terrain_height_map, config.ocean.sea_level);
+    let lakes = LakeMap::new(&terrain_height_map, &ocean, &config.lakes);
+    let flow = FlowMap::new(
+        &terrain_height_map,
+        &hydrology::priority_flood(&terrain_height_map, &ocean),
+        config.flow.method,
+    );
+    let water_surface = lakes.water_surface(&terrain_height_map);
+
+    let river_networks = river_network::networks_from_config(
+        &config.river,
+        &terrain_height_map,
+        &ocean,
+        config.seed,
+    );
+    for network in &river_networks {
+        network.carve(
+            &mut terrain_height_map,
+            &water_surface,
+            config.river.carve_depth,
+            config.river.bank_width,
+        );
+    }
+    let terrain_normals = TerrainNormals::new(&terrain_height_map);
+    let rivers_image = river_generator::create_rivers_image(
+        river_networks.iter().flat_map(|network| network.rivers()),
+        config.terrain.size,
+        texture_res,
+        config.river.falloff,
+    );
+    let river_layer = rivers_image
+        .pixels()
+        .map(|pixel| pixel[0] as f32 / 255.0)
+        .collect::<Vec<_>>();
+
+    let biomes = BiomeMap::new(
+        &terrain_height_map,
+        &terrain_normals,
+        &biome::water_mask(&terrain_height_map, &ocean, &lakes, &rivers_image),
+        ocean.sea_level(),
+        &config.biomes,
+        config.seed,
+    );
+    // Spread the biome indices over [0, 1] so they are visible in the PNG
+    let biome_layer = biomes
+        .biomes()
+        .iter()
+        .map(|biome| *biome as u8 as f32 / (Biome::COUNT - 1) as f32)
+        .collect::<Vec<_>>();
+
+    let resources = ResourceMap::new(&config.resources, &terrain_height_map, &biomes, config.seed)?;
+
+    let mut layers = vec![
+        write_layer(
+            &args.output,
+            "height",
+            texture_res,
+            terrain_height_map.heights(),
+        )?,
+        write_layer(
+            &args.output,
+            "slope",
+            texture_res,
+            terrain_normals.slopes().heights(),
+        )?,
+        write_layer(&args.output, "river", texture_res, &river_layer)?,
+        write_layer(&args.output, "ocean", texture_res, ocean.mask().heights())?,
+        write_layer(&args.output, "lake", texture_res, lakes.levels().heights())?,
+        write_layer(
+            &args.output,
+            "flow_accumulation",
+            texture_res,
+            &flow.accumulation_layer(),
+        )?,
+        write_layer(
+            &args.output,
+            "drainage_basin",
+            texture_res,
+            &flow.basin_layer(),
+        )?,
+        write_layer(
+            &args.output,
+            "moisture",
+            texture_res,
+            biomes.moisture().heights(),
+        )?,
+        write_layer(
+            &args.output,
+            "temperature",
+            texture_res,
+            biomes.temperature().heights(),
+        )?,
+        write_layer(&args.output, "biome", texture_res, &biome_layer)?,
+    ];
+    for kind in ResourceKind::ALL {
+        layers.push(write_layer(
+            &args.output,
+            &format!("resource_{}", kind.name()),
+            texture_res,
+            &resources.deposits(kind).amount_layer(),
+        )?);
+    }
+
+    let manifest = Manifest {
+        config: &config,
+        layers,
+    };
+    fs::write(
+        args.output.join("manifest.json"),
+        serde_json::to_string_pretty(&manifest)?,
+    )?;
+
+    println!("Wrote world to {}", args.output.display());
+
+    Ok(())
+}
+
+/// Writes a square layer of values in `[0, 1]` as a 16-bit PNG, and the unquantized values as
+/// raw f32s
+fn write_layer(
+    directory: &Path,
+    name: &str,
+    resolution: u32,
+    values: &[f32],
+) -> Result<LayerManifest, Box<dyn std::error::Error>> {
+    let png = format!("{name}.png");
+    let raw = format!("{name}.f32");
+
+    let png_pixels = values
+        .iter()
+        .map(|value| (value.clamp(0.0, 1.0) * u16::MAX as f32) as u16)
+        .collect::<Vec<_>>();
+    image::ImageBuffer::<image::Luma<u16>, _>::from_vec(resolution, resolution, png_pixels)
+        .ok_or("layer size does not match its resolution")?
+        .save(directory.join(&png))?;
+
+    let raw_bytes = values
+        .iter()
+        .flat_map(|value| value.to_le_bytes())
+        .collect::<Vec<_>>();
+    fs::write(directory.join(&raw), raw_bytes)?;
+
+    Ok(LayerManifest {
+        name: name.to_string(),
+        png,
+        raw,
+        width: resolution,
+        height: resolution,
+    })
+}
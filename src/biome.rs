@@ -0,0 +1,360 @@
+use gamezap::texture::Texture;
+use rayon::prelude::*;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    heightmap::{Heightmap, NEIGHBOUR_OFFSETS},
+    lake_generator::LakeMap,
+    noise::{self, Noise2D},
+    ocean::OceanMap,
+    terrain_normals::TerrainNormals,
+    world_config::{BiomeConfig, NoiseKind},
+};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+#[repr(u8)]
+/// The kind of land a cell is covered in. The discriminant is the value stored in the biome
+/// layer, and must match the palette in `terrain_frag.wgsl`
+pub enum Biome {
+    Grassland = 0,
+    Forest = 1,
+    Desert = 2,
+    Tundra = 3,
+    Wetland = 4,
+    Beach = 5,
+    Mountain = 6,
+    /// Ocean, lakes and rivers
+    Water = 7,
+}
+
+impl Biome {
+    pub const COUNT: usize = 8;
+    /// Every biome but water
+    pub const LAND: [Self; 7] = [
+        Self::Grassland,
+        Self::Forest,
+        Self::Desert,
+        Self::Tundra,
+        Self::Wetland,
+        Self::Beach,
+        Self::Mountain,
+    ];
+}
+
+/// Marks every cell covered by water: ocean, lakes above the terrain, and the river mask
+pub fn water_mask(
+    heightmap: &Heightmap,
+    ocean: &OceanMap,
+    lakes: &LakeMap,
+    rivers: &image::RgbaImage,
+) -> Vec<bool> {
+    heightmap
+        .heights()
+        .iter()
+        .zip(lakes.levels().heights())
+        .zip(rivers.pixels())
+        .enumerate()
+        .map(|(i, ((height, level), river))| ocean.is_ocean(i) || level > height || river[0] > 0)
+        .collect()
+}
+
+#[derive(Debug, Clone)]
+/// The climate and biome of every cell. Moisture falls off with the distance to the nearest
+/// water, and temperature drops towards the north edge of the map and with altitude, both with
+/// some noise on top
+pub struct BiomeMap {
+    biomes: Vec<Biome>,
+    /// Moisture of every cell, in `[0, 1]`
+    moisture: Heightmap,
+    /// Temperature of every cell, in `[0, 1]`
+    temperature: Heightmap,
+}
+
+impl BiomeMap {
+    /// Number of noise features across the map
+    const NOISE_FEATURES: f32 = 6.0;
+
+    pub fn new(
+        heightmap: &Heightmap,
+        normals: &TerrainNormals,
+        water: &[bool],
+        sea_level: f32,
+        config: &BiomeConfig,
+        seed: u64,
+    ) -> Self {
+        let resolution = heightmap.resolution();
+        let world_size = heightmap.world_size();
+        let water_distance = Self::water_distance(water, resolution);
+        let highest = heightmap
+            .heights()
+            .iter()
+            .copied()
+            .fold(sea_level, f32::max);
+
+        let moisture_noise = noise::generator(NoiseKind::Simplex, 3, 0.5, 0, seed.wrapping_add(1));
+        let temperature_noise =
+            noise::generator(NoiseKind::Simplex, 3, 0.5, 0, seed.wrapping_add(2));
+        let noise_at = |noise: &dyn Noise2D, x: u32, y: u32| {
+            let scale = Self::NOISE_FEATURES / resolution as f32;
+            noise.sample(x as f32 * scale, y as f32 * scale)
+        };
+
+        let moisture = Heightmap::from_fn(resolution, world_size, |x, y| {
+            let distance = water_distance[(y * resolution + x) as usize] * heightmap.cell_size();
+            ((-distance / config.moisture_range).exp()
+                + noise_at(moisture_noise.as_ref(), x, y) * config.moisture_noise)
+                .clamp(0.0, 1.0)
+        });
+
+        let temperature = Heightmap::from_fn(resolution, world_size, |x, y| {
+            let latitude = y as f32 / (resolution - 1).max(1) as f32;
+            let altitude = ((heightmap.get(x, y) - sea_level)
+                / (highest - sea_level).max(f32::EPSILON))
+            .clamp(0.0, 1.0);
+            (config.north_temperature
+                + (config.south_temperature - config.north_temperature) * latitude
+                - altitude * config.lapse_rate
+                + noise_at(temperature_noise.as_ref(), x, y) * config.temperature_noise)
+                .clamp(0.0, 1.0)
+        });
+
+        let biomes = (0..resolution * resolution)
+            .into_par_iter()
+            .map(|i| {
+                let (x, y) = (i % resolution, i / resolution);
+                let height = heightmap.get(x, y);
+                let wet = moisture.get(x, y);
+                let warm = temperature.get(x, y);
+
+                if water[i as usize] {
+                    Biome::Water
+                } else if height > config.mountain_height
+                    || normals.is_steep(x, y, config.mountain_slope)
+                {
+                    Biome::Mountain
+                } else if sea_level > 0.0 && height < sea_level + config.beach_height {
+                    Biome::Beach
+                } else if warm < config.tundra_temperature {
+                    Biome::Tundra
+                } else if wet > config.wetland_moisture {
+                    Biome::Wetland
+                } else if warm > config.desert_temperature && wet < config.desert_moisture {
+                    Biome::Desert
+                } else if wet > config.forest_moisture {
+                    Biome::Forest
+                } else {
+                    Biome::Grassland
+                }
+            })
+            .collect();
+
+        Self {
+            biomes,
+            moisture,
+            temperature,
+        }
+    }
+
+    /// Distance of every cell to the nearest water cell, in cells. Found with a two pass chamfer
+    /// transform over the eight neighbours, which is within a few percent of the true distance
+    fn water_distance(water: &[bool], resolution: u32) -> Vec<f32> {
+        let resolution = resolution as i32;
+        let mut distance = water
+            .iter()
+            .map(|is_water| if *is_water { 0.0 } else { f32::INFINITY })
+            .collect::<Vec<_>>();
+
+        let mut relax = |x: i32, y: i32, offsets: &[(i32, i32)]| {
+            let index = (y * resolution + x) as usize;
+            for (offset_x, offset_y) in offsets {
+                let neighbour_x = x + offset_x;
+                let neighbour_y = y + offset_y;
+                if neighbour_x < 0
+                    || neighbour_y < 0
+                    || neighbour_x >= resolution
+                    || neighbour_y >= resolution
+                {
+                    continue;
+                }
+                let step = if *offset_x != 0 && *offset_y != 0 {
+                    std::f32::consts::SQRT_2
+                } else {
+                    1.0
+                };
+                let candidate = distance[(neighbour_y * resolution + neighbour_x) as usize] + step;
+                distance[index] = distance[index].min(candidate);
+            }
+        };
+
+        // The first four neighbours come before a cell in row order and the last four after it
+        let (before, after) = NEIGHBOUR_OFFSETS.split_at(4);
+        for y in 0..resolution {
+            for x in 0..resolution {
+                relax(x, y, before);
+            }
+        }
+        for y in (0..resolution).rev() {
+            for x in (0..resolution).rev() {
+                relax(x, y, after);
+            }
+        }
+
+        distance
+    }
+
+    pub fn biome(&self, x: u32, y: u32) -> Biome {
+        self.biomes[(y * self.moisture.resolution() + x) as usize]
+    }
+
+    pub fn biomes(&self) -> &[Biome] {
+        &self.biomes
+    }
+
+    pub fn moisture(&self) -> &Heightmap {
+        &self.moisture
+    }
+
+    pub fn temperature(&self) -> &Heightmap {
+        &self.temperature
+    }
+
+    /// The biome of every cell as its index, in a heightmap so it can be uploaded and written
+    /// like the other layers
+    pub fn biome_layer(&self) -> Heightmap {
+        let mut layer = Heightmap::new(self.moisture.resolution(), self.moisture.world_size());
+        for (value, biome) in layer.heights_mut().iter_mut().zip(&self.biomes) {
+            *value = *biome as u8 as f32;
+        }
+        layer
+    }
+
+    /// Uploads the biome indices as a single channel texture. Indices are small whole numbers, so
+    /// they survive the conversion to half floats exactly
+    pub fn create_texture(&self, device: &wgpu::Device, queue: &wgpu::Queue) -> Texture {
+        self.biome_layer()
+            .create_texture(device, queue, "Biome map")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::world_config::LakeConfig;
+
+    /// A config without noise, where temperature only depends on latitude
+    fn test_config() -> BiomeConfig {
+        BiomeConfig {
+            moisture_noise: 0.0,
+            temperature_noise: 0.0,
+            lapse_rate: 0.0,
+            mountain_slope: 90.0,
+            ..Default::default()
+        }
+    }
+
+    fn biome_map(
+        heightmap: &Heightmap,
+        water: &[bool],
+        sea_level: f32,
+        config: &BiomeConfig,
+    ) -> BiomeMap {
+        let normals = TerrainNormals::new(heightmap);
+        BiomeMap::new(heightmap, &normals, water, sea_level, config, 3)
+    }
+
+    #[test]
+    fn water_distance_follows_the_chamfer_steps() {
+        let mut water = vec![false; 25];
+        water[12] = true;
+        let distance = BiomeMap::water_distance(&water, 5);
+
+        assert_eq!(distance[12], 0.0);
+        assert_eq!(distance[13], 1.0);
+        assert_eq!(distance[7], 1.0);
+        assert!((distance[18] - std::f32::consts::SQRT_2).abs() < 1e-6);
+        assert_eq!(distance[14], 2.0);
+        assert!((distance[0] - 2.0 * std::f32::consts::SQRT_2).abs() < 1e-6);
+    }
+
+    #[test]
+    fn moisture_falls_off_with_the_distance_to_water() {
+        // One world unit per cell, with water along the right edge
+        let heightmap = Heightmap::from_fn(17, 15.0, |_, _| 0.5);
+        let water = (0..17 * 17).map(|i| i % 17 == 16).collect::<Vec<_>>();
+        let config = BiomeConfig {
+            moisture_range: 7.0,
+            north_temperature: 0.6,
+            south_temperature: 0.6,
+            ..test_config()
+        };
+        let biomes = biome_map(&heightmap, &water, 0.0, &config);
+
+        for x in 0..17 {
+            let expected = (-((16 - x) as f32) / config.moisture_range).exp();
+            assert!((biomes.moisture().get(x, 8) - expected).abs() < 1e-5);
+        }
+        assert_eq!(biomes.biome(16, 8), Biome::Water);
+        assert_eq!(biomes.biome(15, 8), Biome::Wetland);
+        assert_eq!(biomes.biome(12, 8), Biome::Forest);
+        assert_eq!(biomes.biome(9, 8), Biome::Grassland);
+        assert_eq!(biomes.biome(1, 8), Biome::Desert);
+    }
+
+    #[test]
+    fn cells_are_classified_by_height_and_temperature() {
+        // Temperature rises from 0 along the top edge to 1 along the bottom edge, a beach runs down
+        // the left edge, and a single peak stands in the middle
+        let heightmap = Heightmap::from_fn(9, 7.0, |x, y| match (x, y) {
+            (4, 4) => 0.9,
+            (0, _) => 0.21,
+            _ => 0.5,
+        });
+        let config = BiomeConfig {
+            north_temperature: 0.0,
+            south_temperature: 1.0,
+            ..test_config()
+        };
+        let biomes = biome_map(&heightmap, &[false; 81], 0.2, &config);
+
+        assert_eq!(biomes.biome(4, 4), Biome::Mountain);
+        assert_eq!(biomes.biome(0, 1), Biome::Beach);
+        assert_eq!(biomes.biome(0, 6), Biome::Beach);
+        assert_eq!(biomes.biome(3, 1), Biome::Tundra);
+        assert_eq!(biomes.biome(3, 3), Biome::Grassland);
+        assert_eq!(biomes.biome(3, 6), Biome::Desert);
+        assert!((biomes.temperature().get(3, 6) - 0.75).abs() < 1e-6);
+
+        // Without a sea level above zero there are no beaches
+        let biomes = biome_map(&heightmap, &[false; 81], 0.0, &config);
+        assert_eq!(biomes.biome(0, 6), Biome::Desert);
+    }
+
+    #[test]
+    fn water_mask_covers_ocean_lakes_and_rivers() {
+        // The left column is ocean and a pit at (3, 2) fills up into a lake
+        let heightmap = Heightmap::from_fn(5, 3.0, |x, y| match (x, y) {
+            (0, _) => -1.0,
+            (3, 2) => 0.1,
+            _ => 0.5,
+        });
+        let ocean = OceanMap::new(&heightmap, 0.0);
+        let lakes = LakeMap::new(
+            &heightmap,
+            &ocean,
+            &LakeConfig {
+                max_count: 1,
+                min_area: 1,
+                min_depth: 0.0,
+            },
+        );
+        let mut rivers = image::RgbaImage::new(5, 5);
+        rivers.put_pixel(2, 4, image::Rgba([255, 0, 0, 255]));
+
+        let water = water_mask(&heightmap, &ocean, &lakes, &rivers);
+        for (i, is_water) in water.iter().enumerate() {
+            let (x, y) = (i % 5, i / 5);
+            assert_eq!(*is_water, x == 0 || (x, y) == (3, 2) || (x, y) == (2, 4));
+        }
+    }
+}
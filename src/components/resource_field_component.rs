@@ -0,0 +1,60 @@
+use std::time::Instant;
+
+use gamezap::{new_component, texture::Texture};
+use megalopolis::resource_field::ResourceField;
+
+new_component!(ResourceFieldComponent {
+    field: ResourceField,
+    textures: Vec<Rc<Texture>>,
+    last_update: Instant
+});
+
+impl ResourceFieldComponent {
+    /// Keeps `textures`, made by `ResourceMap::create_resource_maps`, in step with the field
+    pub fn new(field: ResourceField, textures: Vec<Rc<Texture>>) -> Self {
+        Self {
+            field,
+            textures,
+            last_update: Instant::now(),
+            parent: EntityId::MAX,
+            id: (EntityId::MAX, TypeId::of::<Self>(), 0),
+        }
+    }
+
+    pub fn field(&self) -> &ResourceField {
+        &self.field
+    }
+
+    pub fn field_mut(&mut self) -> &mut ResourceField {
+        &mut self.field
+    }
+}
+
+impl ComponentSystem for ResourceFieldComponent {
+    fn update(
+        &mut self,
+        _device: Arc<Device>,
+        queue: Arc<Queue>,
+        _component_map: &mut AllComponents,
+        _engine_details: Rc<Mutex<EngineDetails>>,
+        _engine_systems: Rc<Mutex<EngineSystems>>,
+        _concept_manager: Rc<Mutex<ConceptManager>>,
+        _active_camera_id: Option<EntityId>,
+        _entities: &mut Vec<Entity>,
+        _materials: Option<&mut (Vec<Material>, usize)>,
+        _compute_pipelines: &mut [ComputePipeline],
+    ) {
+        let now = Instant::now();
+        self.field.update((now - self.last_update).as_secs_f32());
+        self.last_update = now;
+
+        if self.field.is_dirty() {
+            let textures = self
+                .textures
+                .iter()
+                .map(|texture| &texture.texture)
+                .collect::<Vec<_>>();
+            self.field.write_textures(&queue, &textures);
+        }
+    }
+}
@@ -0,0 +1,105 @@
+use gamezap::{
+    ecs::components::transform_component::TransformComponent, new_component, texture::Texture,
+};
+use megalopolis::terrain_chunk::{ChunkCoord, ChunkStreamer};
+use nalgebra::{Vector2, Vector3};
+
+/// A chunk entity that stays at a fixed offset from the chunk the camera is in. Its mesh is a flat
+/// grid displaced by the height texture, so following the camera only needs a new texture and a
+/// new position, and the mesh keeps the level of detail picked for its offset
+pub struct ChunkSlot {
+    pub entity: EntityId,
+    pub height_texture: Rc<Texture>,
+    pub normal_texture: Rc<Texture>,
+    pub offset: ChunkCoord,
+    pub coord: Option<ChunkCoord>,
+}
+
+new_component!(TerrainStreamingComponent {
+    streamer: ChunkStreamer,
+    slots: Vec<ChunkSlot>
+});
+
+impl TerrainStreamingComponent {
+    /// Needs one slot for every chunk the streamer draws at once
+    pub fn new(streamer: ChunkStreamer, slots: Vec<ChunkSlot>) -> Self {
+        assert_eq!(streamer.visible_count(), slots.len());
+        Self {
+            streamer,
+            slots,
+            parent: EntityId::MAX,
+            id: (EntityId::MAX, TypeId::of::<Self>(), 0),
+        }
+    }
+}
+
+impl ComponentSystem for TerrainStreamingComponent {
+    fn update(
+        &mut self,
+        _device: Arc<Device>,
+        queue: Arc<Queue>,
+        component_map: &mut AllComponents,
+        _engine_details: Rc<Mutex<EngineDetails>>,
+        _engine_systems: Rc<Mutex<EngineSystems>>,
+        concept_manager: Rc<Mutex<ConceptManager>>,
+        active_camera_id: Option<EntityId>,
+        _entities: &mut Vec<Entity>,
+        _materials: Option<&mut (Vec<Material>, usize)>,
+        _compute_pipelines: &mut [ComputePipeline],
+    ) {
+        let Some(camera_id) = active_camera_id else {
+            return;
+        };
+
+        let this_concept_manager = concept_manager.lock().unwrap();
+        let camera_position = *this_concept_manager
+            .get_concept::<Vector3<f32>>(
+                (camera_id, TypeId::of::<TransformComponent>(), 0),
+                "position".to_string(),
+            )
+            .unwrap();
+        drop(this_concept_manager);
+
+        let changes = self
+            .streamer
+            .update(Vector2::new(camera_position.x, camera_position.z));
+        if changes.shown.is_empty() && changes.hidden.is_empty() {
+            return;
+        }
+
+        let center = self.streamer.center().unwrap();
+        let chunk_size = self.streamer.config().size;
+
+        for slot in &mut self.slots {
+            let coord = ChunkCoord::new(center.x + slot.offset.x, center.y + slot.offset.y);
+            let chunk = self.streamer.chunk(coord).unwrap();
+            chunk
+                .heightmap
+                .write_texture(&queue, &slot.height_texture.texture);
+            chunk
+                .normals
+                .write_texture(&queue, &slot.normal_texture.texture);
+
+            let this_concept_manager = concept_manager.lock().unwrap();
+            let slot_position = *this_concept_manager
+                .get_concept::<Vector3<f32>>(
+                    (slot.entity, TypeId::of::<TransformComponent>(), 0),
+                    "position".to_string(),
+                )
+                .unwrap();
+            drop(this_concept_manager);
+
+            let origin = coord.origin(chunk_size);
+            for comp in component_map.get_mut(&slot.entity).unwrap() {
+                if let Some(transform) = comp.as_any_mut().downcast_mut::<TransformComponent>() {
+                    transform.apply_translation(
+                        concept_manager.clone(),
+                        Vector3::new(origin.x, 0.0, origin.y) - slot_position,
+                    );
+                }
+            }
+
+            slot.coord = Some(coord);
+        }
+    }
+}
@@ -0,0 +1,344 @@
+use nalgebra::Vector2;
+use rand::{Rng, SeedableRng};
+use rand_chacha::ChaCha8Rng;
+use rayon::prelude::*;
+
+use crate::{
+    heightmap::{Heightmap, HEIGHT_SCALE, NEIGHBOUR_OFFSETS},
+    world_config::{HydraulicErosionConfig, ThermalErosionConfig},
+};
+
+/// Simulates water droplets rolling down the heightmap, picking up sediment on steep descents and
+/// dropping it where they slow down or climb. Droplets run one after another, so the result only
+/// depends on the seed. Any sediment still carried when a droplet dies is dropped where it stopped,
+/// keeping the total amount of material on the map constant.
+pub fn hydraulic_erosion(heightmap: &mut Heightmap, config: &HydraulicErosionConfig, seed: u64) {
+    let resolution = heightmap.resolution();
+    if resolution < 2 {
+        return;
+    }
+
+    let mut rng = ChaCha8Rng::seed_from_u64(seed);
+    let brush = erosion_brush(config.erosion_radius);
+    let max_position = (resolution - 1) as f32;
+
+    for _ in 0..config.droplet_count {
+        let mut position = Vector2::new(
+            rng.gen_range(0.0..max_position),
+            rng.gen_range(0.0..max_position),
+        );
+        let mut direction = Vector2::<f32>::zeros();
+        let mut speed = config.initial_speed;
+        let mut water = config.initial_water;
+        let mut sediment = 0.0;
+
+        for _ in 0..config.max_lifetime {
+            let (height, gradient) = height_and_gradient(heightmap, position);
+
+            direction = direction * config.inertia - gradient * (1.0 - config.inertia);
+            if direction.norm_squared() == 0.0 {
+                break;
+            }
+            direction.normalize_mut();
+
+            let new_position = position + direction;
+            if new_position.x < 0.0
+                || new_position.y < 0.0
+                || new_position.x >= max_position
+                || new_position.y >= max_position
+            {
+                break;
+            }
+
+            let height_delta = heightmap.sample(new_position.x, new_position.y) - height;
+            let capacity = (-height_delta * speed * water * config.sediment_capacity)
+                .max(config.min_sediment_capacity);
+
+            if sediment > capacity || height_delta > 0.0 {
+                // Going uphill, fill the pit behind the droplet. Otherwise drop the excess
+                let deposit_amount = if height_delta > 0.0 {
+                    height_delta.min(sediment)
+                } else {
+                    (sediment - capacity) * config.deposition
+                };
+                sediment -= deposit_amount;
+                deposit(heightmap, position, deposit_amount);
+            } else {
+                // Never dig deeper than the height difference, otherwise droplets carve holes
+                let erode_amount = ((capacity - sediment) * config.erosion).min(-height_delta);
+                sediment += erode(heightmap, &brush, position, erode_amount);
+            }
+
+            speed = (speed * speed - height_delta * config.gravity)
+                .max(0.0)
+                .sqrt();
+            water *= 1.0 - config.evaporation;
+            position = new_position;
+        }
+
+        deposit(heightmap, position, sediment);
+    }
+}
+
+/// Returns the bilinearly interpolated height and its gradient at a point inside the grid
+fn height_and_gradient(heightmap: &Heightmap, position: Vector2<f32>) -> (f32, Vector2<f32>) {
+    let x = position.x.floor() as u32;
+    let y = position.y.floor() as u32;
+    let u = position.x - x as f32;
+    let v = position.y - y as f32;
+
+    let top_left = heightmap.get(x, y);
+    let top_right = heightmap.get(x + 1, y);
+    let bottom_left = heightmap.get(x, y + 1);
+    let bottom_right = heightmap.get(x + 1, y + 1);
+
+    let gradient = Vector2::new(
+        (top_right - top_left) * (1.0 - v) + (bottom_right - bottom_left) * v,
+        (bottom_left - top_left) * (1.0 - u) + (bottom_right - top_right) * u,
+    );
+    let height = top_left * (1.0 - u) * (1.0 - v)
+        + top_right * u * (1.0 - v)
+        + bottom_left * (1.0 - u) * v
+        + bottom_right * u * v;
+
+    (height, gradient)
+}
+
+/// Spreads `amount` over the four cells around `position`, weighted bilinearly
+fn deposit(heightmap: &mut Heightmap, position: Vector2<f32>, amount: f32) {
+    let x = position.x.floor() as u32;
+    let y = position.y.floor() as u32;
+    let u = position.x - x as f32;
+    let v = position.y - y as f32;
+
+    for (cell_x, cell_y, weight) in [
+        (x, y, (1.0 - u) * (1.0 - v)),
+        (x + 1, y, u * (1.0 - v)),
+        (x, y + 1, (1.0 - u) * v),
+        (x + 1, y + 1, u * v),
+    ] {
+        let height = heightmap.get(cell_x, cell_y);
+        heightmap.set(cell_x, cell_y, height + amount * weight);
+    }
+}
+
+/// Cell offsets within `radius` of the origin, weighted by their closeness
+fn erosion_brush(radius: usize) -> Vec<(i32, i32, f32)> {
+    let radius = radius as i32;
+    (-radius..=radius)
+        .flat_map(|y| (-radius..=radius).map(move |x| (x, y)))
+        .filter_map(|(x, y)| {
+            let weight = radius as f32 - ((x * x + y * y) as f32).sqrt();
+            (weight > 0.0).then_some((x, y, weight))
+        })
+        .collect()
+}
+
+/// Removes up to `amount` of material around `position`, never taking a cell below zero, and
+/// returns how much was actually removed
+fn erode(
+    heightmap: &mut Heightmap,
+    brush: &[(i32, i32, f32)],
+    position: Vector2<f32>,
+    amount: f32,
+) -> f32 {
+    let resolution = heightmap.resolution() as i32;
+    let center_x = position.x.floor() as i32;
+    let center_y = position.y.floor() as i32;
+
+    let cells = brush
+        .iter()
+        .map(|(x, y, weight)| (center_x + x, center_y + y, *weight))
+        .filter(|(x, y, _)| *x >= 0 && *y >= 0 && *x < resolution && *y < resolution)
+        .collect::<Vec<_>>();
+    let total_weight: f32 = cells.iter().map(|(_, _, weight)| weight).sum();
+
+    let mut removed = 0.0;
+    for (x, y, weight) in cells {
+        let height = heightmap.get(x as u32, y as u32);
+        let cell_amount = (amount * weight / total_weight).min(height);
+        heightmap.set(x as u32, y as u32, height - cell_amount);
+        removed += cell_amount;
+    }
+
+    removed
+}
+
+/// Relaxes slopes steeper than the talus angle by sliding material from each cell to its lower
+/// neighbours. Every iteration computes all outflows from the same snapshot before applying them,
+/// so the result does not depend on the order cells are visited in.
+pub fn thermal_erosion(heightmap: &mut Heightmap, config: &ThermalErosionConfig) {
+    let resolution = heightmap.resolution() as i32;
+    let talus_slope = config.talus_angle.to_radians().tan();
+    // Largest height difference, in normalized units, that each neighbour can sit below a cell
+    let talus_differences = NEIGHBOUR_OFFSETS.map(|(x, y)| {
+        let distance = ((x * x + y * y) as f32).sqrt() * heightmap.cell_size();
+        talus_slope * distance / HEIGHT_SCALE
+    });
+
+    for _ in 0..config.iterations {
+        let heights = heightmap.heights();
+
+        let outflows = (0..resolution * resolution)
+            .into_par_iter()
+            .map(|i| {
+                let x = i % resolution;
+                let y = i / resolution;
+                let height = heights[i as usize];
+
+                let mut excesses = [0.0_f32; 8];
+                for (n, (offset_x, offset_y)) in NEIGHBOUR_OFFSETS.iter().enumerate() {
+                    let neighbour_x = x + offset_x;
+                    let neighbour_y = y + offset_y;
+                    if neighbour_x < 0
+                        || neighbour_y < 0
+                        || neighbour_x >= resolution
+                        || neighbour_y >= resolution
+                    {
+                        continue;
+                    }
+                    let difference =
+                        height - heights[(neighbour_y * resolution + neighbour_x) as usize];
+                    excesses[n] = (difference - talus_differences[n]).max(0.0);
+                }
+
+                let total_excess: f32 = excesses.iter().sum();
+                if total_excess == 0.0 {
+                    return [0.0; 8];
+                }
+
+                // Moving half of the steepest excess at most keeps a cell from dropping below
+                // the neighbour it slides into
+                let max_excess = excesses.iter().copied().fold(0.0, f32::max);
+                let moved = config.rate * max_excess / 2.0;
+                excesses.map(|excess| moved * excess / total_excess)
+            })
+            .collect::<Vec<_>>();
+
+        heightmap
+            .heights_mut()
+            .par_iter_mut()
+            .enumerate()
+            .for_each(|(i, height)| {
+                let x = i as i32 % resolution;
+                let y = i as i32 / resolution;
+
+                *height -= outflows[i].iter().sum::<f32>();
+                for (n, (offset_x, offset_y)) in NEIGHBOUR_OFFSETS.iter().enumerate() {
+                    let neighbour_x = x - offset_x;
+                    let neighbour_y = y - offset_y;
+                    if neighbour_x < 0
+                        || neighbour_y < 0
+                        || neighbour_x >= resolution
+                        || neighbour_y >= resolution
+                    {
+                        continue;
+                    }
+                    *height += outflows[(neighbour_y * resolution + neighbour_x) as usize][n];
+                }
+            });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::perlin_noise::PerlinNoise;
+
+    fn test_heightmap() -> Heightmap {
+        let perlin = PerlinNoise::new(4, 0.5, 7);
+        Heightmap::from_perlin(&perlin, 8, 63, 10.0)
+    }
+
+    fn total_mass(heightmap: &Heightmap) -> f64 {
+        heightmap
+            .heights()
+            .iter()
+            .map(|height| *height as f64)
+            .sum()
+    }
+
+    #[test]
+    fn hydraulic_erosion_conserves_mass() {
+        let mut heightmap = test_heightmap();
+        let mass_before = total_mass(&heightmap);
+
+        let config = HydraulicErosionConfig {
+            droplet_count: 5_000,
+            ..Default::default()
+        };
+        hydraulic_erosion(&mut heightmap, &config, 3);
+
+        let mass_after = total_mass(&heightmap);
+        assert!(
+            ((mass_after - mass_before) / mass_before).abs() < 1e-4,
+            "mass changed from {mass_before} to {mass_after}"
+        );
+    }
+
+    #[test]
+    fn hydraulic_erosion_changes_terrain() {
+        let original = test_heightmap();
+        let mut heightmap = original.clone();
+
+        let config = HydraulicErosionConfig {
+            droplet_count: 1_000,
+            ..Default::default()
+        };
+        hydraulic_erosion(&mut heightmap, &config, 3);
+
+        assert_ne!(original.heights(), heightmap.heights());
+    }
+
+    #[test]
+    fn hydraulic_erosion_is_deterministic_per_seed() {
+        let config = HydraulicErosionConfig {
+            droplet_count: 1_000,
+            ..Default::default()
+        };
+
+        let mut first = test_heightmap();
+        let mut second = test_heightmap();
+        let mut other_seed = test_heightmap();
+        hydraulic_erosion(&mut first, &config, 11);
+        hydraulic_erosion(&mut second, &config, 11);
+        hydraulic_erosion(&mut other_seed, &config, 12);
+
+        assert_eq!(first.heights(), second.heights());
+        assert_ne!(first.heights(), other_seed.heights());
+    }
+
+    #[test]
+    fn thermal_erosion_conserves_mass_and_flattens_slopes() {
+        let mut heightmap = test_heightmap();
+        let mass_before = total_mass(&heightmap);
+        let steepest_before = steepest_difference(&heightmap);
+
+        let config = ThermalErosionConfig {
+            iterations: 50,
+            talus_angle: 10.0,
+            rate: 0.5,
+        };
+        thermal_erosion(&mut heightmap, &config);
+
+        let mass_after = total_mass(&heightmap);
+        assert!(
+            ((mass_after - mass_before) / mass_before).abs() < 1e-4,
+            "mass changed from {mass_before} to {mass_after}"
+        );
+        assert!(steepest_difference(&heightmap) < steepest_before);
+    }
+
+    fn steepest_difference(heightmap: &Heightmap) -> f32 {
+        let resolution = heightmap.resolution();
+        (0..resolution - 1)
+            .flat_map(|y| (0..resolution - 1).map(move |x| (x, y)))
+            .map(|(x, y)| {
+                let height = heightmap.get(x, y);
+                (height - heightmap.get(x + 1, y))
+                    .abs()
+                    .max((height - heightmap.get(x, y + 1)).abs())
+            })
+            .fold(0.0, f32::max)
+    }
+}
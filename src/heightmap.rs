@@ -0,0 +1,306 @@
+use gamezap::texture::Texture;
+use nalgebra::Vector2;
+use rayon::iter::{IndexedParallelIterator, IntoParallelRefMutIterator, ParallelIterator};
+
+use crate::{noise::Noise2D, perlin_noise::PerlinNoise};
+
+/// World space height of a cell with a normalized height of 1. Must match `TERRAIN_AMPLITUDE` in
+/// `terrain_vert.wgsl`.
+pub const HEIGHT_SCALE: f32 = 2.0;
+
+/// Offsets of the 8 neighbours of a cell
+pub const NEIGHBOUR_OFFSETS: [(i32, i32); 8] = [
+    (-1, -1),
+    (0, -1),
+    (1, -1),
+    (-1, 0),
+    (1, 0),
+    (-1, 1),
+    (0, 1),
+    (1, 1),
+];
+
+#[derive(Debug, Clone)]
+/// A square grid of heights, stored row by row. Heights are normalized so that the raw noise
+/// range maps to `[0, 1]`, leaving the vertical scale to the shader. `world_size` is the side
+/// length of the terrain mesh the grid is drawn on, in world units. The mesh has
+/// `resolution - 2` quads along each side and samples cell `(x + 1, y + 1)` at its vertex
+/// `(x, y)`, so the grid has one border cell before the first vertex.
+pub struct Heightmap {
+    heights: Vec<f32>,
+    resolution: u32,
+    world_size: f32,
+}
+
+impl Heightmap {
+    pub fn new(resolution: u32, world_size: f32) -> Self {
+        Self {
+            heights: vec![0.0; (resolution * resolution) as usize],
+            resolution,
+            world_size,
+        }
+    }
+
+    /// Builds a heightmap by evaluating `height_fn` at every `(x, y)` cell in parallel
+    pub fn from_fn<F>(resolution: u32, world_size: f32, height_fn: F) -> Self
+    where
+        F: Fn(u32, u32) -> f32 + Sync,
+    {
+        let mut heightmap = Self::new(resolution, world_size);
+        heightmap
+            .heights
+            .par_iter_mut()
+            .enumerate()
+            .for_each(|(i, height)| {
+                let x = i as u32 % resolution;
+                let y = i as u32 / resolution;
+                *height = height_fn(x, y);
+            });
+        heightmap
+    }
+
+    /// Generates the terrain heightmap from fractal Perlin noise. The map has a one cell border
+    /// on every side, so it is `terrain_resolution + 2` cells wide, matching the offset used when
+    /// sampling in the vertex shader.
+    pub fn from_perlin(
+        perlin: &PerlinNoise,
+        perlin_size: usize,
+        terrain_resolution: usize,
+        terrain_size: f32,
+    ) -> Self {
+        Self::from_noise(perlin, perlin_size, terrain_resolution, terrain_size)
+    }
+
+    /// Generates the terrain heightmap from any noise, sampled over `[0, noise_size]` along each
+    /// side the same way as [`Heightmap::from_perlin`]
+    pub fn from_noise(
+        noise: &(impl Noise2D + ?Sized),
+        noise_size: usize,
+        terrain_resolution: usize,
+        terrain_size: f32,
+    ) -> Self {
+        let resolution = terrain_resolution as u32 + 2;
+        let cells_per_unit = ((terrain_resolution + 1) / noise_size) as f32;
+
+        Self::from_fn(resolution, terrain_size, |x, y| {
+            let noise_val = noise.sample(x as f32 / cells_per_unit, y as f32 / cells_per_unit);
+            (noise_val + 1.0) / 2.0
+        })
+    }
+
+    /// The heightmap without `border` cells on every side, for a mesh that many quads shorter at
+    /// each end. Panics if that leaves no cells
+    pub fn cropped(&self, border: u32) -> Self {
+        assert!(
+            border < self.resolution.div_ceil(2),
+            "cannot crop {border} cells off each side of a heightmap {} cells wide",
+            self.resolution
+        );
+        Self::from_fn(
+            self.resolution - 2 * border,
+            self.world_size - 2.0 * border as f32 * self.cell_size(),
+            |x, y| self.get(x + border, y + border),
+        )
+    }
+
+    pub fn resolution(&self) -> u32 {
+        self.resolution
+    }
+
+    pub fn world_size(&self) -> f32 {
+        self.world_size
+    }
+
+    /// The world space distance between two neighbouring cells, which is the quad size of the
+    /// terrain mesh
+    pub fn cell_size(&self) -> f32 {
+        self.world_size / self.resolution.saturating_sub(2).max(1) as f32
+    }
+
+    /// World position of a cell, measured from the corner of the terrain mesh
+    pub fn cell_position(&self, x: u32, y: u32) -> Vector2<f32> {
+        Vector2::new(x as f32 - 1.0, y as f32 - 1.0) * self.cell_size()
+    }
+
+    /// Fractional cell coordinates of a world position, the inverse of
+    /// [`Heightmap::cell_position`]
+    pub fn position_to_cell(&self, position: Vector2<f32>) -> Vector2<f32> {
+        position / self.cell_size() + Vector2::new(1.0, 1.0)
+    }
+
+    pub fn heights(&self) -> &[f32] {
+        &self.heights
+    }
+
+    pub fn heights_mut(&mut self) -> &mut [f32] {
+        &mut self.heights
+    }
+
+    pub fn get(&self, x: u32, y: u32) -> f32 {
+        self.heights[(y * self.resolution + x) as usize]
+    }
+
+    pub fn set(&mut self, x: u32, y: u32, height: f32) {
+        self.heights[(y * self.resolution + x) as usize] = height;
+    }
+
+    /// Bilinearly interpolates the height at a fractional cell coordinate. Coordinates outside
+    /// the grid are clamped to the border.
+    pub fn sample(&self, x: f32, y: f32) -> f32 {
+        let max = (self.resolution - 1) as f32;
+        let x = x.clamp(0.0, max);
+        let y = y.clamp(0.0, max);
+
+        let x_floor = x.floor() as u32;
+        let y_floor = y.floor() as u32;
+        let x_ceil = (x_floor + 1).min(self.resolution - 1);
+        let y_ceil = (y_floor + 1).min(self.resolution - 1);
+
+        let top_lerp = PerlinNoise::lerp(
+            self.get(x_floor, y_floor),
+            self.get(x_ceil, y_floor),
+            x.fract(),
+        );
+        let bottom_lerp = PerlinNoise::lerp(
+            self.get(x_floor, y_ceil),
+            self.get(x_ceil, y_ceil),
+            x.fract(),
+        );
+
+        PerlinNoise::lerp(top_lerp, bottom_lerp, y.fract())
+    }
+
+    /// Uploads the heights as a single channel `R16Float` texture. Half floats give far more
+    /// elevation steps than an 8-bit channel, and unlike `R32Float` they stay filterable without
+    /// requiring extra device features.
+    pub fn create_texture(
+        &self,
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        label: &str,
+    ) -> Texture {
+        let size = wgpu::Extent3d {
+            width: self.resolution,
+            height: self.resolution,
+            depth_or_array_layers: 1,
+        };
+
+        let texture = device.create_texture(&wgpu::TextureDescriptor {
+            label: Some(label),
+            size,
+            mip_level_count: 1,
+            sample_count: 1,
+            dimension: wgpu::TextureDimension::D2,
+            format: wgpu::TextureFormat::R16Float,
+            usage: wgpu::TextureUsages::TEXTURE_BINDING | wgpu::TextureUsages::COPY_DST,
+            view_formats: &[],
+        });
+
+        self.write_texture(queue, &texture);
+
+        let view = texture.create_view(&wgpu::TextureViewDescriptor::default());
+        let sampler = device.create_sampler(&wgpu::SamplerDescriptor {
+            label: Some(&format!("{label} sampler")),
+            address_mode_u: wgpu::AddressMode::ClampToEdge,
+            address_mode_v: wgpu::AddressMode::ClampToEdge,
+            address_mode_w: wgpu::AddressMode::ClampToEdge,
+            mag_filter: wgpu::FilterMode::Linear,
+            min_filter: wgpu::FilterMode::Linear,
+            mipmap_filter: wgpu::FilterMode::Nearest,
+            ..Default::default()
+        });
+
+        Texture {
+            texture,
+            view,
+            sampler,
+        }
+    }
+
+    /// Uploads the heights into an existing texture made by [`Heightmap::create_texture`] for a
+    /// heightmap of the same resolution
+    pub fn write_texture(&self, queue: &wgpu::Queue, texture: &wgpu::Texture) {
+        let half_heights = self
+            .heights
+            .iter()
+            .map(|height| half::f16::from_f32(*height))
+            .collect::<Vec<_>>();
+
+        queue.write_texture(
+            wgpu::ImageCopyTexture {
+                texture,
+                mip_level: 0,
+                origin: wgpu::Origin3d::ZERO,
+                aspect: wgpu::TextureAspect::All,
+            },
+            bytemuck::cast_slice(&half_heights),
+            wgpu::ImageDataLayout {
+                offset: 0,
+                bytes_per_row: Some(std::mem::size_of::<half::f16>() as u32 * self.resolution),
+                rows_per_image: Some(self.resolution),
+            },
+            wgpu::Extent3d {
+                width: self.resolution,
+                height: self.resolution,
+                depth_or_array_layers: 1,
+            },
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Four quads along each side of a mesh 8 world units wide, so cells are 2 units apart
+    fn heightmap() -> Heightmap {
+        Heightmap::from_fn(6, 8.0, |x, y| (x + 10 * y) as f32)
+    }
+
+    #[test]
+    fn cell_positions_round_trip() {
+        let heightmap = heightmap();
+        assert_eq!(heightmap.cell_size(), 2.0);
+        assert_eq!(heightmap.cell_position(1, 1), Vector2::zeros());
+        assert_eq!(heightmap.cell_position(5, 3), Vector2::new(8.0, 4.0));
+
+        for y in 0..6 {
+            for x in 0..6 {
+                let position = heightmap.cell_position(x, y);
+                let cell = heightmap.position_to_cell(position);
+                assert!((cell - Vector2::new(x as f32, y as f32)).norm() < 1e-6);
+                assert_eq!(heightmap.sample(cell.x, cell.y), heightmap.get(x, y));
+            }
+        }
+    }
+
+    #[test]
+    fn sampling_interpolates_and_clamps_to_the_grid() {
+        let heightmap = heightmap();
+        assert_eq!(heightmap.sample(1.5, 2.0), 21.5);
+        assert_eq!(heightmap.sample(2.0, 2.5), 27.0);
+        assert_eq!(heightmap.sample(1.5, 1.5), 16.5);
+
+        // Positions past the edge read the nearest edge cell
+        assert_eq!(heightmap.sample(-3.0, 2.0), heightmap.get(0, 2));
+        assert_eq!(heightmap.sample(9.0, 7.5), heightmap.get(5, 5));
+        let cell = heightmap.position_to_cell(Vector2::new(-10.0, 30.0));
+        assert_eq!(heightmap.sample(cell.x, cell.y), heightmap.get(0, 5));
+    }
+
+    #[test]
+    fn cropping_keeps_the_cell_size() {
+        let heightmap = heightmap();
+        let cropped = heightmap.cropped(1);
+        assert_eq!(cropped.resolution(), 4);
+        assert_eq!(cropped.cell_size(), heightmap.cell_size());
+        assert_eq!(cropped.get(0, 0), heightmap.get(1, 1));
+        assert_eq!(cropped.get(3, 3), heightmap.get(4, 4));
+    }
+
+    #[test]
+    #[should_panic(expected = "cannot crop 3 cells")]
+    fn cropping_everything_panics() {
+        heightmap().cropped(3);
+    }
+}
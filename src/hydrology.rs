@@ -0,0 +1,523 @@
+use std::{cmp::Ordering, collections::BinaryHeap};
+
+use rayon::prelude::*;
+
+use crate::{
+    heightmap::{Heightmap, NEIGHBOUR_OFFSETS},
+    ocean::OceanMap,
+    world_config::FlowMethod,
+};
+
+#[derive(Debug, Clone)]
+/// How water drains off the heightmap, found by flooding it inwards from the ocean, or from the
+/// map edges when there is no ocean
+pub struct Drainage {
+    /// Height of the water surface at every cell if each depression were filled up to the point
+    /// where it spills over
+    pub filled: Vec<f32>,
+    /// Index of the cell each cell drains into on its way to the ocean or map edge. Those cells
+    /// have no downstream cell
+    pub downstream: Vec<Option<usize>>,
+}
+
+#[derive(Debug, PartialEq)]
+struct FloodCell {
+    level: f32,
+    index: usize,
+}
+
+impl Eq for FloodCell {}
+
+impl Ord for FloodCell {
+    /// Reversed so that the binary heap pops the lowest cell first. Ties are broken by index to
+    /// keep the flood deterministic
+    fn cmp(&self, other: &Self) -> Ordering {
+        other
+            .level
+            .total_cmp(&self.level)
+            .then_with(|| other.index.cmp(&self.index))
+    }
+}
+
+impl PartialOrd for FloodCell {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+/// Priority-flood over the heightmap. Starting from every ocean cell, or every edge cell if there
+/// is no ocean, the lowest unvisited cell is repeatedly expanded into its neighbours, which then
+/// drain into it. A neighbour lower than the current water level sits in a depression and is
+/// raised to that level in [`Drainage::filled`].
+pub fn priority_flood(heightmap: &Heightmap, ocean: &OceanMap) -> Drainage {
+    let resolution = heightmap.resolution() as i32;
+    let heights = heightmap.heights();
+
+    let mut filled = heights.to_vec();
+    let mut downstream = vec![None; heights.len()];
+    let mut visited = vec![false; heights.len()];
+    let mut queue = BinaryHeap::new();
+
+    let has_ocean = ocean.has_ocean();
+    for y in 0..resolution {
+        for x in 0..resolution {
+            let is_outlet = if has_ocean {
+                ocean.is_ocean((y * resolution + x) as usize)
+            } else {
+                x == 0 || y == 0 || x == resolution - 1 || y == resolution - 1
+            };
+            if is_outlet {
+                let index = (y * resolution + x) as usize;
+                visited[index] = true;
+                queue.push(FloodCell {
+                    level: heights[index],
+                    index,
+                });
+            }
+        }
+    }
+
+    while let Some(FloodCell { level, index }) = queue.pop() {
+        let x = index as i32 % resolution;
+        let y = index as i32 / resolution;
+
+        for (offset_x, offset_y) in NEIGHBOUR_OFFSETS {
+            let neighbour_x = x + offset_x;
+            let neighbour_y = y + offset_y;
+            if neighbour_x < 0
+                || neighbour_y < 0
+                || neighbour_x >= resolution
+                || neighbour_y >= resolution
+            {
+                continue;
+            }
+
+            let neighbour = (neighbour_y * resolution + neighbour_x) as usize;
+            if visited[neighbour] {
+                continue;
+            }
+            visited[neighbour] = true;
+
+            filled[neighbour] = heights[neighbour].max(level);
+            downstream[neighbour] = Some(index);
+            queue.push(FloodCell {
+                level: filled[neighbour],
+                index: neighbour,
+            });
+        }
+    }
+
+    Drainage { filled, downstream }
+}
+
+impl Drainage {
+    /// Follows the drainage from `source` until it reaches the ocean or runs off the edge of the
+    /// map, returning every cell index along the way
+    pub fn flow_path(&self, source: usize) -> Vec<usize> {
+        let mut path = vec![source];
+        let mut current = source;
+        while let Some(next) = self.downstream[current] {
+            path.push(next);
+            current = next;
+        }
+        path
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+/// Where the water of a cell flows to
+pub enum FlowDirection {
+    /// The water leaves the map or reaches the ocean here
+    Outlet,
+    /// All of the water flows into one neighbour
+    Single(usize),
+    /// The water is shared between two neighbours, with the first one receiving the given
+    /// fraction of it
+    Split(usize, usize, f32),
+}
+
+impl FlowDirection {
+    /// The neighbour receiving most of the water
+    pub fn main_receiver(&self) -> Option<usize> {
+        match *self {
+            FlowDirection::Outlet => None,
+            FlowDirection::Single(receiver) => Some(receiver),
+            FlowDirection::Split(first, second, share) => {
+                Some(if share >= 0.5 { first } else { second })
+            }
+        }
+    }
+}
+
+/// Cardinal and diagonal neighbour offsets of the eight triangular facets around a cell, used by
+/// D-infinity
+const FACETS: [((i32, i32), (i32, i32)); 8] = [
+    ((1, 0), (1, -1)),
+    ((0, -1), (1, -1)),
+    ((0, -1), (-1, -1)),
+    ((-1, 0), (-1, -1)),
+    ((-1, 0), (-1, 1)),
+    ((0, 1), (-1, 1)),
+    ((0, 1), (1, 1)),
+    ((1, 0), (1, 1)),
+];
+
+#[derive(Debug, Clone)]
+/// Flow direction of every cell over the depression-filled terrain, along with how much water
+/// collects in each cell and which drainage basin it belongs to
+pub struct FlowMap {
+    pub directions: Vec<FlowDirection>,
+    /// Number of cells whose water passes through each cell, counting the cell itself
+    pub accumulation: Vec<f32>,
+    /// Index of the outlet cell each cell eventually drains into. Cells sharing an outlet form a
+    /// drainage basin
+    pub basins: Vec<usize>,
+}
+
+impl FlowMap {
+    pub fn new(heightmap: &Heightmap, drainage: &Drainage, method: FlowMethod) -> Self {
+        let directions = match method {
+            FlowMethod::D8 => Self::d8_directions(heightmap, drainage),
+            FlowMethod::DInfinity => Self::d_infinity_directions(heightmap, drainage),
+        };
+        let accumulation = Self::accumulate(&directions);
+        let basins = Self::basins(&directions);
+
+        Self {
+            directions,
+            accumulation,
+            basins,
+        }
+    }
+
+    /// Sends all of the water of each cell to its steepest downhill neighbour. Flat cells inside
+    /// filled depressions follow the drainage towards the spill point instead
+    fn d8_directions(heightmap: &Heightmap, drainage: &Drainage) -> Vec<FlowDirection> {
+        let resolution = heightmap.resolution() as i32;
+        let filled = &drainage.filled;
+
+        (0..filled.len())
+            .into_par_iter()
+            .map(|i| {
+                let Some(downstream) = drainage.downstream[i] else {
+                    return FlowDirection::Outlet;
+                };
+                let x = i as i32 % resolution;
+                let y = i as i32 / resolution;
+
+                NEIGHBOUR_OFFSETS
+                    .iter()
+                    .filter_map(|(offset_x, offset_y)| {
+                        let neighbour_x = x + offset_x;
+                        let neighbour_y = y + offset_y;
+                        if neighbour_x < 0
+                            || neighbour_y < 0
+                            || neighbour_x >= resolution
+                            || neighbour_y >= resolution
+                        {
+                            return None;
+                        }
+                        let neighbour = (neighbour_y * resolution + neighbour_x) as usize;
+                        let distance = ((offset_x * offset_x + offset_y * offset_y) as f32).sqrt();
+                        let slope = (filled[i] - filled[neighbour]) / distance;
+                        (slope > 0.0).then_some((neighbour, slope))
+                    })
+                    .max_by(|a, b| a.1.total_cmp(&b.1))
+                    .map_or(FlowDirection::Single(downstream), |(neighbour, _)| {
+                        FlowDirection::Single(neighbour)
+                    })
+            })
+            .collect()
+    }
+
+    /// Tarboton's D-infinity. The steepest slope is searched over the eight triangular facets
+    /// around each cell, and the water is split between the two neighbours spanning that facet
+    /// depending on how close the slope direction is to each of them
+    fn d_infinity_directions(heightmap: &Heightmap, drainage: &Drainage) -> Vec<FlowDirection> {
+        let resolution = heightmap.resolution() as i32;
+        let filled = &drainage.filled;
+        let quarter_pi = std::f32::consts::FRAC_PI_4;
+
+        (0..filled.len())
+            .into_par_iter()
+            .map(|i| {
+                let Some(downstream) = drainage.downstream[i] else {
+                    return FlowDirection::Outlet;
+                };
+                let x = i as i32 % resolution;
+                let y = i as i32 / resolution;
+                let index = |(offset_x, offset_y): (i32, i32)| {
+                    let neighbour_x = x + offset_x;
+                    let neighbour_y = y + offset_y;
+                    (neighbour_x >= 0
+                        && neighbour_y >= 0
+                        && neighbour_x < resolution
+                        && neighbour_y < resolution)
+                        .then_some((neighbour_y * resolution + neighbour_x) as usize)
+                };
+
+                FACETS
+                    .iter()
+                    .filter_map(|(cardinal_offset, diagonal_offset)| {
+                        let cardinal = index(*cardinal_offset)?;
+                        let diagonal = index(*diagonal_offset)?;
+
+                        let cardinal_slope = filled[i] - filled[cardinal];
+                        let cross_slope = filled[cardinal] - filled[diagonal];
+                        let mut angle = cross_slope.atan2(cardinal_slope);
+                        let mut slope = cardinal_slope.hypot(cross_slope);
+                        if angle < 0.0 {
+                            angle = 0.0;
+                            slope = cardinal_slope;
+                        } else if angle > quarter_pi {
+                            angle = quarter_pi;
+                            slope = (filled[i] - filled[diagonal]) / std::f32::consts::SQRT_2;
+                        }
+
+                        (slope > 0.0).then_some((cardinal, diagonal, angle, slope))
+                    })
+                    .max_by(|a, b| a.3.total_cmp(&b.3))
+                    .map_or(
+                        FlowDirection::Single(downstream),
+                        |(cardinal, diagonal, angle, _)| {
+                            let cardinal_share = 1.0 - angle / quarter_pi;
+                            if cardinal_share >= 1.0 {
+                                FlowDirection::Single(cardinal)
+                            } else if cardinal_share <= 0.0 {
+                                FlowDirection::Single(diagonal)
+                            } else {
+                                FlowDirection::Split(cardinal, diagonal, cardinal_share)
+                            }
+                        },
+                    )
+            })
+            .collect()
+    }
+
+    /// Passes water from every cell to its receivers, handling each cell only once everything
+    /// upstream of it is done
+    fn accumulate(directions: &[FlowDirection]) -> Vec<f32> {
+        let mut upstream_counts = vec![0_u32; directions.len()];
+        for direction in directions {
+            match *direction {
+                FlowDirection::Outlet => {}
+                FlowDirection::Single(receiver) => upstream_counts[receiver] += 1,
+                FlowDirection::Split(first, second, _) => {
+                    upstream_counts[first] += 1;
+                    upstream_counts[second] += 1;
+                }
+            }
+        }
+
+        let mut accumulation = vec![1.0; directions.len()];
+        let mut ready = (0..directions.len())
+            .filter(|i| upstream_counts[*i] == 0)
+            .collect::<Vec<_>>();
+
+        while let Some(i) = ready.pop() {
+            let amount = accumulation[i];
+            let shares = match directions[i] {
+                FlowDirection::Outlet => [None, None],
+                FlowDirection::Single(receiver) => [Some((receiver, amount)), None],
+                FlowDirection::Split(first, second, share) => [
+                    Some((first, amount * share)),
+                    Some((second, amount * (1.0 - share))),
+                ],
+            };
+
+            for (receiver, amount) in shares.into_iter().flatten() {
+                accumulation[receiver] += amount;
+                upstream_counts[receiver] -= 1;
+                if upstream_counts[receiver] == 0 {
+                    ready.push(receiver);
+                }
+            }
+        }
+
+        accumulation
+    }
+
+    /// Follows the main receiver of every cell down to its outlet, remembering the outlet of every
+    /// cell along the way so each path is only walked once
+    fn basins(directions: &[FlowDirection]) -> Vec<usize> {
+        let mut basins = vec![usize::MAX; directions.len()];
+        let mut path = Vec::new();
+
+        for start in 0..directions.len() {
+            let mut current = start;
+            while basins[current] == usize::MAX {
+                path.push(current);
+                match directions[current].main_receiver() {
+                    Some(receiver) => current = receiver,
+                    None => {
+                        basins[current] = current;
+                        break;
+                    }
+                }
+            }
+
+            let outlet = basins[current];
+            for cell in path.drain(..) {
+                basins[cell] = outlet;
+            }
+        }
+
+        basins
+    }
+
+    /// Flow accumulation on a log scale, normalized to `[0, 1]`, since a handful of river cells
+    /// collect orders of magnitude more water than the rest
+    pub fn accumulation_layer(&self) -> Vec<f32> {
+        let max = self
+            .accumulation
+            .iter()
+            .copied()
+            .fold(1.0, f32::max)
+            .ln_1p();
+        self.accumulation
+            .iter()
+            .map(|accumulation| accumulation.ln_1p() / max)
+            .collect()
+    }
+
+    /// Every drainage basin numbered by its outlet order, normalized to `[0, 1]`
+    pub fn basin_layer(&self) -> Vec<f32> {
+        let mut outlets = self.basins.clone();
+        outlets.sort_unstable();
+        outlets.dedup();
+        let basin_count = outlets.len().max(2) - 1;
+
+        self.basins
+            .iter()
+            .map(|outlet| outlets.binary_search(outlet).unwrap() as f32 / basin_count as f32)
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Sea level below the whole terrain, so water drains off the map edges
+    fn no_ocean(heightmap: &Heightmap) -> OceanMap {
+        OceanMap::new(heightmap, -10.0)
+    }
+
+    /// A ridge down the middle column between two strips of ocean along the left and right edges
+    fn ridge() -> (Heightmap, OceanMap) {
+        let heightmap = Heightmap::from_fn(7, 5.0, |x, _| match x {
+            0 | 6 => -1.0,
+            _ => 0.5 - (x as f32 - 3.0).abs() * 0.1,
+        });
+        let ocean = OceanMap::new(&heightmap, 0.0);
+        (heightmap, ocean)
+    }
+
+    #[test]
+    fn priority_flood_fills_depressions_to_their_spill_point() {
+        // A pit at (2, 2) inside a ring of hills with a pass at (2, 1)
+        let heightmap = Heightmap::from_fn(5, 3.0, |x, y| match (x, y) {
+            (0 | 4, _) | (_, 0 | 4) => 0.1,
+            (2, 2) => 0.2,
+            (2, 1) => 0.4,
+            _ => 0.6,
+        });
+        let drainage = priority_flood(&heightmap, &no_ocean(&heightmap));
+
+        for (i, (filled, height)) in drainage.filled.iter().zip(heightmap.heights()).enumerate() {
+            let expected = if i == 2 * 5 + 2 { 0.4 } else { *height };
+            assert_eq!(*filled, expected);
+        }
+
+        // The pit drains over the pass and off the top edge
+        let path = drainage.flow_path(2 * 5 + 2);
+        assert_eq!(path[..2], [2 * 5 + 2, 5 + 2]);
+        let outlet = *path.last().unwrap();
+        assert_eq!(outlet / 5, 0);
+        assert!(drainage.downstream[outlet].is_none());
+    }
+
+    #[test]
+    fn accumulation_sums_to_the_cell_count_at_the_outlets() {
+        let heightmap = Heightmap::from_fn(9, 7.0, |x, y| ((x * 7 + y * 13) % 5) as f32 * 0.1);
+        let drainage = priority_flood(&heightmap, &no_ocean(&heightmap));
+
+        for method in [FlowMethod::D8, FlowMethod::DInfinity] {
+            let flow = FlowMap::new(&heightmap, &drainage, method);
+            let total = flow
+                .directions
+                .iter()
+                .zip(&flow.accumulation)
+                .filter(|(direction, _)| **direction == FlowDirection::Outlet)
+                .map(|(_, accumulation)| accumulation)
+                .sum::<f32>();
+            assert!((total - 81.0).abs() < 1e-3);
+            assert!(flow
+                .accumulation
+                .iter()
+                .all(|accumulation| *accumulation >= 1.0));
+        }
+    }
+
+    #[test]
+    fn d_infinity_splits_water_by_the_slope_direction() {
+        // A plane falling towards the left and top, twice as steep to the left
+        let heightmap = Heightmap::from_fn(7, 5.0, |x, y| x as f32 * 0.1 + y as f32 * 0.05);
+        let drainage = priority_flood(&heightmap, &no_ocean(&heightmap));
+        let flow = FlowMap::new(&heightmap, &drainage, FlowMethod::DInfinity);
+
+        let expected_share = 1.0 - 0.5_f32.atan() / std::f32::consts::FRAC_PI_4;
+        for y in 1..6 {
+            for x in 1..6 {
+                let i = y * 7 + x;
+                let FlowDirection::Split(cardinal, diagonal, share) = flow.directions[i] else {
+                    panic!("cell {i} does not split its water");
+                };
+                assert_eq!((cardinal, diagonal), (i - 1, i - 8));
+                assert!((share - expected_share).abs() < 1e-5);
+            }
+        }
+
+        // Every cell passes on exactly the water it holds
+        let mut received = vec![0.0; flow.directions.len()];
+        for (direction, accumulation) in flow.directions.iter().zip(&flow.accumulation) {
+            match *direction {
+                FlowDirection::Outlet => {}
+                FlowDirection::Single(receiver) => received[receiver] += accumulation,
+                FlowDirection::Split(first, second, share) => {
+                    assert!(share > 0.0 && share < 1.0);
+                    received[first] += accumulation * share;
+                    received[second] += accumulation * (1.0 - share);
+                }
+            }
+        }
+        for (received, accumulation) in received.iter().zip(&flow.accumulation) {
+            assert!((received + 1.0 - accumulation).abs() < 1e-4);
+        }
+    }
+
+    #[test]
+    fn basins_are_labelled_by_their_outlet() {
+        let (heightmap, ocean) = ridge();
+        let drainage = priority_flood(&heightmap, &ocean);
+        let flow = FlowMap::new(&heightmap, &drainage, FlowMethod::D8);
+
+        for y in 0..7 {
+            for x in 0..7 {
+                let expected = match x {
+                    0..=2 => y * 7,
+                    // The ridge is equally steep on both sides
+                    3 => continue,
+                    _ => y * 7 + 6,
+                };
+                assert_eq!(flow.basins[y * 7 + x], expected);
+            }
+        }
+
+        // Each of the fourteen ocean cells is an outlet with its own basin
+        let layer = flow.basin_layer();
+        assert_eq!(layer[0], 0.0);
+        assert_eq!(layer[6 * 7 + 6], 1.0);
+        assert!((layer[7] - 2.0 / 13.0).abs() < 1e-6);
+    }
+}
@@ -0,0 +1,227 @@
+use gamezap::texture::Texture;
+
+use crate::{
+    heightmap::{Heightmap, NEIGHBOUR_OFFSETS},
+    hydrology,
+    ocean::OceanMap,
+    world_config::LakeConfig,
+};
+
+#[derive(Debug, Clone)]
+/// A closed basin of the heightmap filled with water up to the height where it spills over
+pub struct Lake {
+    /// Indices of the cells under water
+    pub cells: Vec<usize>,
+    /// Height of the water surface, in normalized height units
+    pub level: f32,
+}
+
+#[derive(Debug, Clone)]
+/// Every lake on the map, along with the water level of each cell. Lakes are found with the same
+/// priority-flood drainage that downhill rivers follow, so a river running into a basin crosses
+/// the lake and leaves it where it spills over. Basins draining into the ocean are never lakes.
+pub struct LakeMap {
+    pub lakes: Vec<Lake>,
+    /// Water level of every cell, zero where there is no lake
+    levels: Heightmap,
+}
+
+impl LakeMap {
+    pub fn new(heightmap: &Heightmap, ocean: &OceanMap, config: &LakeConfig) -> Self {
+        let resolution = heightmap.resolution();
+        let mut levels = Heightmap::new(resolution, heightmap.world_size());
+        if config.max_count == 0 {
+            return Self {
+                lakes: Vec::new(),
+                levels,
+            };
+        }
+
+        let drainage = hydrology::priority_flood(heightmap, ocean);
+        let heights = heightmap.heights();
+        let submerged = |i: usize| drainage.filled[i] > heights[i] && !ocean.is_ocean(i);
+
+        let mut visited = vec![false; heights.len()];
+        let mut lakes = Vec::new();
+        for start in 0..heights.len() {
+            if visited[start] || !submerged(start) {
+                continue;
+            }
+
+            let cells = Self::flood_basin(resolution, start, &mut visited, submerged);
+            let depth = cells
+                .iter()
+                .map(|i| drainage.filled[*i] - heights[*i])
+                .fold(0.0, f32::max);
+            if cells.len() < config.min_area || depth < config.min_depth {
+                continue;
+            }
+
+            let level = cells
+                .iter()
+                .map(|i| drainage.filled[*i])
+                .fold(0.0, f32::max);
+            lakes.push(Lake { cells, level });
+        }
+
+        lakes.sort_by_key(|lake| std::cmp::Reverse(lake.cells.len()));
+        lakes.truncate(config.max_count);
+
+        for lake in &lakes {
+            for cell in &lake.cells {
+                levels.heights_mut()[*cell] = lake.level;
+            }
+        }
+
+        Self { lakes, levels }
+    }
+
+    /// Collects every submerged cell connected to `start`
+    fn flood_basin(
+        resolution: u32,
+        start: usize,
+        visited: &mut [bool],
+        submerged: impl Fn(usize) -> bool,
+    ) -> Vec<usize> {
+        let resolution = resolution as i32;
+        let mut cells = Vec::new();
+        let mut stack = vec![start];
+        visited[start] = true;
+
+        while let Some(index) = stack.pop() {
+            cells.push(index);
+            let x = index as i32 % resolution;
+            let y = index as i32 / resolution;
+
+            for (offset_x, offset_y) in NEIGHBOUR_OFFSETS {
+                let neighbour_x = x + offset_x;
+                let neighbour_y = y + offset_y;
+                if neighbour_x < 0
+                    || neighbour_y < 0
+                    || neighbour_x >= resolution
+                    || neighbour_y >= resolution
+                {
+                    continue;
+                }
+
+                let neighbour = (neighbour_y * resolution + neighbour_x) as usize;
+                if !visited[neighbour] && submerged(neighbour) {
+                    visited[neighbour] = true;
+                    stack.push(neighbour);
+                }
+            }
+        }
+
+        cells
+    }
+
+    /// Water level of every cell, zero where there is no lake
+    pub fn levels(&self) -> &Heightmap {
+        &self.levels
+    }
+
+    /// The terrain with every lake filled up to its water level. Rivers take their water level
+    /// from this surface, so they run flat across lakes instead of digging through them
+    pub fn water_surface(&self, heightmap: &Heightmap) -> Heightmap {
+        let mut surface = heightmap.clone();
+        for (height, level) in surface.heights_mut().iter_mut().zip(self.levels.heights()) {
+            *height = height.max(*level);
+        }
+        surface
+    }
+
+    /// Uploads the water levels as a single channel texture. A fragment is under a lake wherever
+    /// the level is above the terrain height
+    pub fn create_texture(&self, device: &wgpu::Device, queue: &wgpu::Queue) -> Texture {
+        self.levels.create_texture(device, queue, "Lake level map")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn lake_config(min_area: usize, min_depth: f32) -> LakeConfig {
+        LakeConfig {
+            max_count: 4,
+            min_area,
+            min_depth,
+        }
+    }
+
+    /// A 3x3 basin in the middle of a ring of hills, with a pass at (3, 1) that it spills over
+    fn basin() -> Heightmap {
+        Heightmap::from_fn(7, 5.0, |x, y| match (x, y) {
+            (0 | 6, _) | (_, 0 | 6) => 0.3,
+            (3, 1) => 0.55,
+            (1 | 5, _) | (_, 1 | 5) => 0.6,
+            (3, 3) => 0.1,
+            _ => 0.2,
+        })
+    }
+
+    #[test]
+    fn closed_basins_fill_up_to_their_spill_height() {
+        let heightmap = basin();
+        let ocean = OceanMap::new(&heightmap, 0.0);
+        let lakes = LakeMap::new(&heightmap, &ocean, &lake_config(1, 0.0));
+
+        assert_eq!(lakes.lakes.len(), 1);
+        let lake = &lakes.lakes[0];
+        assert_eq!(lake.level, 0.55);
+        let mut cells = lake.cells.clone();
+        cells.sort_unstable();
+        assert_eq!(cells, vec![16, 17, 18, 23, 24, 25, 30, 31, 32]);
+
+        let surface = lakes.water_surface(&heightmap);
+        assert_eq!(surface.get(3, 3), 0.55);
+        assert_eq!(surface.get(1, 1), 0.6);
+        assert_eq!(lakes.levels().get(1, 1), 0.0);
+    }
+
+    #[test]
+    fn small_and_shallow_lakes_are_dropped() {
+        let heightmap = basin();
+        let ocean = OceanMap::new(&heightmap, 0.0);
+
+        assert_eq!(
+            LakeMap::new(&heightmap, &ocean, &lake_config(9, 0.45))
+                .lakes
+                .len(),
+            1
+        );
+        assert!(LakeMap::new(&heightmap, &ocean, &lake_config(10, 0.0))
+            .lakes
+            .is_empty());
+        assert!(LakeMap::new(&heightmap, &ocean, &lake_config(1, 0.5))
+            .lakes
+            .is_empty());
+
+        let disabled = LakeConfig {
+            max_count: 0,
+            ..lake_config(1, 0.0)
+        };
+        let lakes = LakeMap::new(&heightmap, &ocean, &disabled);
+        assert!(lakes.lakes.is_empty());
+        assert!(lakes.levels().heights().iter().all(|level| *level == 0.0));
+    }
+
+    #[test]
+    fn ocean_basins_never_become_lakes() {
+        // The left columns are ocean with a deep hole in them, and an inland pit below sea level
+        // at (4, 3) is cut off from the ocean by higher land
+        let heightmap = Heightmap::from_fn(7, 5.0, |x, y| match (x, y) {
+            (1, 3) => -2.0,
+            (0 | 1, _) => -1.0,
+            (4, 3) => -0.5,
+            _ => 0.5,
+        });
+        let ocean = OceanMap::new(&heightmap, 0.0);
+        let lakes = LakeMap::new(&heightmap, &ocean, &lake_config(1, 0.0));
+
+        assert_eq!(lakes.lakes.len(), 1);
+        assert_eq!(lakes.lakes[0].cells, vec![3 * 7 + 4]);
+        assert_eq!(lakes.lakes[0].level, 0.5);
+        assert_eq!(lakes.levels().get(1, 3), 0.0);
+    }
+}
@@ -0,0 +1,21 @@
+pub mod biome;
+pub mod erosion;
+pub mod heightmap;
+pub mod hydrology;
+pub mod lake_generator;
+pub mod noise;
+pub mod noise_graph;
+pub mod ocean;
+pub mod perlin_noise;
+pub mod poisson_disk;
+pub mod resource_field;
+pub mod resource_generator;
+pub mod river_generator;
+pub mod river_network;
+pub mod simplex_noise;
+pub mod terrain_chunk;
+pub mod terrain_mesh;
+pub mod terrain_normals;
+pub mod value_noise;
+pub mod world_config;
+pub mod worley_noise;
@@ -1,25 +1,47 @@
-use std::rc::Rc;
+use std::{rc::Rc, sync::Arc};
 
 use gamezap::{
     ecs::{components as core_components, material::Material, scene},
-    model::Vertex,
+    texture::Texture,
+};
+use megalopolis::{
+    biome::{self, BiomeMap},
+    erosion,
+    heightmap::Heightmap,
+    lake_generator::LakeMap,
+    noise,
+    ocean::{self, OceanMap},
+    resource_field::ResourceField,
+    resource_generator::ResourceMap,
+    river_generator, river_network,
+    terrain_chunk::{ChunkCoord, ChunkStreamer},
+    terrain_mesh::{self, Diagonal, Winding},
+    terrain_normals::TerrainNormals,
+    world_config::WorldConfig,
 };
 use nalgebra::Vector3;
-use perlin_noise::PerlinNoise;
-use rayon::iter::{IntoParallelIterator, ParallelIterator};
-use resource_generator::ResourceMap;
-use river_generator::River;
+
+use crate::components::{
+    resource_field_component::ResourceFieldComponent,
+    terrain_streaming_component::{ChunkSlot, TerrainStreamingComponent},
+};
 
 pub mod components {
     pub mod camera_control_component;
+    pub mod resource_field_component;
+    pub mod terrain_streaming_component;
 }
 
-pub mod perlin_noise;
-pub mod resource_generator;
-pub mod river_generator;
-
 #[tokio::main]
 async fn main() {
+    let config_path = std::env::args()
+        .nth(1)
+        .unwrap_or_else(|| "world.toml".to_string());
+    let world_config = WorldConfig::load_or_default(&config_path).unwrap_or_else(|err| {
+        eprintln!("{err}");
+        std::process::exit(1);
+    });
+
     let sdl_context = sdl2::init().unwrap();
     let video_subsystem = sdl_context.video().unwrap();
     let event_pump = sdl_context.event_pump().unwrap();
@@ -86,11 +108,36 @@ async fn main() {
     scene.set_active_camera(camera_entity);
 
     // Terrain
-    let terrain_resolution = 300;
-    let terrain_size = 20.0;
+    if world_config.chunks.load_radius > 0 {
+        create_streamed_terrain(&mut scene, &world_config, &device, &queue);
+    } else {
+        create_terrain(&mut scene, &world_config, &device, &queue);
+    }
+
+    engine.create_scene(scene);
+    engine.main_loop();
+}
+
+/// Generates the whole map up front, with erosion, water and resources, and draws it as a single
+/// mesh
+fn create_terrain(
+    scene: &mut scene::Scene,
+    world_config: &WorldConfig,
+    device: &Arc<wgpu::Device>,
+    queue: &wgpu::Queue,
+) {
+    let concept_manager = scene.get_concept_manager();
+
+    let terrain_resolution = world_config.terrain.resolution;
+    let terrain_size = world_config.terrain.size;
 
-    let (terrain_vertices, terrain_indices) =
-        terrain_mesh_creation(terrain_resolution, terrain_size / terrain_resolution as f32);
+    let (terrain_vertices, terrain_indices) = terrain_mesh::grid_mesh(
+        terrain_resolution,
+        terrain_size / terrain_resolution as f32,
+        1,
+        Winding::default(),
+        Diagonal::Uniform,
+    );
 
     let terrain_mesh_component = core_components::mesh_component::MeshComponent::new(
         concept_manager.clone(),
@@ -105,54 +152,119 @@ async fn main() {
         Vector3::new(1.0, 1.0, 1.0),
     );
 
-    let terrain_seed = 0;
+    let terrain_seed = world_config.seed;
 
-    let perlin_size = 30;
+    let perlin_size = world_config.terrain.perlin_size;
 
-    let texture_res = terrain_resolution as u32 + 2;
+    let terrain_noise = noise::from_config(&world_config.terrain, terrain_seed);
 
-    let perlin = PerlinNoise::new(perlin_size, 5, 0.5, terrain_seed);
+    let mut terrain_height_map = Heightmap::from_noise(
+        &terrain_noise,
+        perlin_size,
+        terrain_resolution,
+        terrain_size,
+    );
 
-    let terrain_height_pixels = (0..texture_res * texture_res)
-        .into_par_iter()
-        .flat_map(|i| {
-            let x = i % texture_res;
-            let y = i / texture_res;
+    ocean::apply_falloff(&mut terrain_height_map, &world_config.ocean, terrain_seed);
 
-            let perlin_val = perlin.reverse_octave_evaluate(
-                x as f32 / ((terrain_resolution + 1) / perlin_size) as f32,
-                y as f32 / ((terrain_resolution + 1) / perlin_size) as f32,
-            );
-            let height = ((perlin_val + 1.0) / 2.0 * 255.0) as u8;
-            [0, height, 0, 0]
-        })
-        .collect::<Vec<_>>();
+    erosion::hydraulic_erosion(
+        &mut terrain_height_map,
+        &world_config.hydraulic_erosion,
+        terrain_seed,
+    );
+    erosion::thermal_erosion(&mut terrain_height_map, &world_config.thermal_erosion);
 
-    let terrain_height_map =
-        image::RgbaImage::from_vec(texture_res, texture_res, terrain_height_pixels).unwrap();
+    let texture_res = terrain_height_map.resolution();
 
-    let mut river = River::new(terrain_size, 1.0, terrain_seed);
+    let ocean = OceanMap::new(&terrain_height_map, world_config.ocean.sea_level);
+    let lakes = LakeMap::new(&terrain_height_map, &ocean, &world_config.lakes);
+    let water_surface = lakes.water_surface(&terrain_height_map);
 
-    river.random_shift(20);
+    let river_networks = river_network::networks_from_config(
+        &world_config.river,
+        &terrain_height_map,
+        &ocean,
+        terrain_seed,
+    );
 
-    let terrain_height_texture = Rc::new(
-        gamezap::texture::Texture::from_rgba(
-            &device,
-            &queue,
-            &terrain_height_map,
-            Some("Terrain height map"),
-            true,
-            true,
-        )
-        .unwrap(),
+    for network in &river_networks {
+        network.carve(
+            &mut terrain_height_map,
+            &water_surface,
+            world_config.river.carve_depth,
+            world_config.river.bank_width,
+        );
+    }
+
+    let terrain_normals = TerrainNormals::new(&terrain_height_map);
+
+    let terrain_height_texture =
+        Rc::new(terrain_height_map.create_texture(device, queue, "Terrain height map"));
+
+    let terrain_normal_texture = Rc::new(terrain_normals.create_texture(device, queue));
+
+    let rivers_image = river_generator::create_rivers_image(
+        river_networks.iter().flat_map(|network| network.rivers()),
+        terrain_size,
+        texture_res,
+        world_config.river.falloff,
     );
 
-    let river_height_texture =
-        Rc::new(river.create_texture(&device, &queue, terrain_size, texture_res));
+    let river_height_texture = Rc::new(river_generator::rivers_texture_from_image(
+        device,
+        queue,
+        &rivers_image,
+    ));
+
+    let biomes = BiomeMap::new(
+        &terrain_height_map,
+        &terrain_normals,
+        &biome::water_mask(&terrain_height_map, &ocean, &lakes, &rivers_image),
+        ocean.sea_level(),
+        &world_config.biomes,
+        terrain_seed,
+    );
 
-    let resource = ResourceMap::new(5, 30.0, 100.0, 3, 30.0, 15.0, texture_res, 0);
+    let biome_texture = Rc::new(biomes.create_texture(device, queue));
+
+    let resources = ResourceMap::new(
+        &world_config.resources,
+        &terrain_height_map,
+        &biomes,
+        terrain_seed,
+    )
+    .unwrap_or_else(|err| {
+        eprintln!("{err}");
+        std::process::exit(1);
+    });
+
+    let resource_map_textures = resources
+        .create_resource_maps(device, queue)
+        .into_iter()
+        .map(Rc::new)
+        .collect::<Vec<_>>();
 
-    let resource_map_texture = Rc::new(resource.create_resource_map(&device, &queue, texture_res));
+    // The overlay finds nothing in empty resource maps, so the terrain keeps its biome colours
+    let overlay_textures = if world_config.resources.overlay {
+        resource_map_textures.clone()
+    } else {
+        let empty = Rc::new(
+            Texture::from_rgba(
+                device,
+                queue,
+                &image::RgbaImage::new(1, 1),
+                Some("Empty resource map"),
+                true,
+                true,
+            )
+            .unwrap(),
+        );
+        vec![empty.clone(), empty]
+    };
+
+    let lake_level_texture = Rc::new(lakes.create_texture(device, queue));
+
+    let ocean_mask_texture = Rc::new(ocean.create_texture(device, queue));
 
     let terrain_material = Material::new(
         "shaders/terrain_vert.wgsl",
@@ -160,7 +272,12 @@ async fn main() {
         vec![
             terrain_height_texture,
             river_height_texture,
-            resource_map_texture,
+            overlay_textures[0].clone(),
+            lake_level_texture,
+            ocean_mask_texture,
+            terrain_normal_texture,
+            biome_texture,
+            overlay_textures[1].clone(),
         ],
         None,
         true,
@@ -177,57 +294,140 @@ async fn main() {
         Some((vec![terrain_material], 0)),
     );
 
-    engine.create_scene(scene);
-    engine.main_loop();
+    let resource_field = ResourceField::new(
+        &resources,
+        &world_config.resources,
+        terrain_height_map.cell_size(),
+    );
+    let _resource_entity = scene.create_entity(
+        0,
+        true,
+        vec![Box::new(ResourceFieldComponent::new(
+            resource_field,
+            resource_map_textures,
+        ))],
+        None,
+    );
 }
 
-/// Creates a 2-dimensional grid.
-/// Specify the resolution of the grid (number of vertices),
-/// and the size of every quad of the mesh.
-/// The final mesh is of size `(resolution * quad_size)^2`
-fn terrain_mesh_creation(resolution: usize, quad_size: f32) -> (Vec<Vertex>, Vec<u32>) {
-    let vertices = (0..resolution)
-        .flat_map(|i| {
-            (0..resolution)
-                .flat_map(|j| {
-                    let i = i as f32;
-                    let j = j as f32;
-                    let i_scaled = i * quad_size;
-                    let j_scaled = j * quad_size;
-                    // let resolution = resolution as f32;
-                    // let grid_size = resolution * quad_size;
-                    vec![
-                        Vertex {
-                            position: [j_scaled, 0.0, i_scaled],
-                            tex_coords: [j, i],
-                            normal: [0.0, 1.0, 0.0],
-                        },
-                        Vertex {
-                            position: [j_scaled + quad_size, 0.0, i_scaled],
-                            tex_coords: [(j + 1.0), i],
-                            normal: [0.0, 1.0, 0.0],
-                        },
-                        Vertex {
-                            position: [j_scaled + quad_size, 0.0, i_scaled + quad_size],
-                            tex_coords: [(j + 1.0), (i + 1.0)],
-                            normal: [0.0, 1.0, 0.0],
-                        },
-                        Vertex {
-                            position: [j_scaled, 0.0, i_scaled + quad_size],
-                            tex_coords: [j, (i + 1.0)],
-                            normal: [0.0, 1.0, 0.0],
-                        },
-                    ]
-                })
-                .collect::<Vec<_>>()
-        })
-        .collect();
-    let indices = (0..resolution * resolution)
-        .flat_map(|i| {
-            let i = 4 * i as u32;
-            vec![i, i + 1, i + 2, /* | */ i, i + 2, i + 3]
+/// Draws the terrain as chunks streamed around the camera. Chunks only sample the terrain noise,
+/// since erosion and water are computed over the whole map, so their other layers are left empty
+fn create_streamed_terrain(
+    scene: &mut scene::Scene,
+    world_config: &WorldConfig,
+    device: &Arc<wgpu::Device>,
+    queue: &wgpu::Queue,
+) {
+    let concept_manager = scene.get_concept_manager();
+    let chunks = &world_config.chunks;
+    let tile_resolution = chunks.resolution as u32 + 2;
+
+    // One mesh per level of detail, shared by every chunk drawn at that level
+    let lod_meshes = (0..=chunks.lod_levels)
+        .map(|lod| terrain_mesh::chunk_mesh(chunks, lod))
+        .collect::<Vec<_>>();
+
+    let empty_layer = Rc::new(Heightmap::new(tile_resolution, chunks.size).create_texture(
+        device,
+        queue,
+        "Empty chunk layer",
+    ));
+
+    // Empty in every channel, so the resource overlay finds nothing on chunks
+    let empty_resources = Rc::new(
+        Texture::from_rgba(
+            device,
+            queue,
+            &image::RgbaImage::new(tile_resolution, tile_resolution),
+            Some("Empty chunk resources"),
+            true,
+            true,
+        )
+        .unwrap(),
+    );
+
+    let streamer = ChunkStreamer::new(
+        noise::from_config(&world_config.terrain, world_config.seed),
+        &world_config.terrain,
+        chunks,
+    );
+
+    let slots = streamer
+        .visible_around(ChunkCoord::new(0, 0))
+        .into_iter()
+        .map(|offset| {
+            let height_texture =
+                Rc::new(Heightmap::new(tile_resolution, chunks.size).create_texture(
+                    device,
+                    queue,
+                    "Chunk height map",
+                ));
+            let normal_texture = Rc::new(
+                TerrainNormals::new(&Heightmap::new(tile_resolution, chunks.size))
+                    .create_texture(device, queue),
+            );
+
+            let chunk_material = Material::new(
+                "shaders/terrain_vert.wgsl",
+                "shaders/terrain_frag.wgsl",
+                vec![
+                    height_texture.clone(),
+                    empty_layer.clone(),
+                    empty_resources.clone(),
+                    empty_layer.clone(),
+                    empty_layer.clone(),
+                    normal_texture.clone(),
+                    empty_layer.clone(),
+                    empty_resources.clone(),
+                ],
+                None,
+                true,
+                device.clone(),
+            );
+
+            // Every slot keeps its offset from the camera chunk, so its level of detail never
+            // changes
+            let lod = terrain_mesh::lod_level(offset.distance(ChunkCoord::new(0, 0)), chunks);
+            let (chunk_vertices, chunk_indices) = &lod_meshes[lod as usize];
+            let chunk_mesh_component = core_components::mesh_component::MeshComponent::new(
+                concept_manager.clone(),
+                chunk_vertices.clone(),
+                chunk_indices.clone(),
+            );
+
+            // Moved to its chunk by the streaming component once the camera is known
+            let chunk_transform_component =
+                core_components::transform_component::TransformComponent::new(
+                    concept_manager.clone(),
+                    Vector3::zeros(),
+                    algoe::rotor::Rotor3::default(),
+                    Vector3::new(1.0, 1.0, 1.0),
+                );
+
+            let entity = scene.create_entity(
+                0,
+                true,
+                vec![
+                    Box::new(chunk_mesh_component),
+                    Box::new(chunk_transform_component),
+                ],
+                Some((vec![chunk_material], 0)),
+            );
+
+            ChunkSlot {
+                entity,
+                height_texture,
+                normal_texture,
+                offset,
+                coord: None,
+            }
         })
         .collect();
 
-    (vertices, indices)
+    let _streaming_entity = scene.create_entity(
+        0,
+        true,
+        vec![Box::new(TerrainStreamingComponent::new(streamer, slots))],
+        None,
+    );
 }
@@ -0,0 +1,123 @@
+use crate::{
+    noise_graph,
+    perlin_noise::PerlinNoise,
+    simplex_noise::{OpenSimplex2Noise, SimplexNoise},
+    value_noise::ValueNoise,
+    world_config::{NoiseKind, TerrainConfig},
+    worley_noise::{WorleyNoise, WorleyReturn},
+};
+
+/// A 2D noise function. Values lie roughly in `[-1, 1]` for a single octave, and coordinates are
+/// measured so that one unit is about the size of the smallest feature
+pub trait Noise2D: Send + Sync {
+    fn sample(&self, x: f32, y: f32) -> f32;
+}
+
+impl<N: Noise2D + ?Sized> Noise2D for Box<N> {
+    fn sample(&self, x: f32, y: f32) -> f32 {
+        (**self).sample(x, y)
+    }
+}
+
+#[derive(Debug, Clone)]
+/// Sums octaves of a noise function the same way [`PerlinNoise::reverse_octave_evaluate`] does.
+/// The first octave is the coarsest and has full amplitude, and every following octave doubles
+/// the frequency and scales the amplitude by `persistence`, ending at one feature per unit.
+pub struct Fractal<N> {
+    pub noise: N,
+    pub octaves: usize,
+    pub persistence: f32,
+}
+
+impl<N: Noise2D> Fractal<N> {
+    /// Shifts every octave so that they do not all line up at the origin
+    const OCTAVE_OFFSET: f32 = 17.37;
+
+    pub fn new(noise: N, octaves: usize, persistence: f32) -> Self {
+        Self {
+            noise,
+            octaves,
+            persistence,
+        }
+    }
+}
+
+impl<N: Noise2D> Noise2D for Fractal<N> {
+    fn sample(&self, x: f32, y: f32) -> f32 {
+        (0..self.octaves)
+            .map(|octave| {
+                let scale = 2.0_f32.powi((self.octaves - 1 - octave) as i32);
+                let offset = octave as f32 * Self::OCTAVE_OFFSET;
+                self.persistence.powi(octave as i32)
+                    * self.noise.sample(x / scale + offset, y / scale + offset)
+            })
+            .sum()
+    }
+}
+
+/// Builds the terrain noise picked in the config, or its noise graph if it has one. Every
+/// generator is sampled over `[0, perlin_size]`, so switching between them keeps the scale of the
+/// terrain
+pub fn from_config(config: &TerrainConfig, seed: u64) -> Box<dyn Noise2D> {
+    match &config.graph {
+        Some(graph) => noise_graph::build(graph, seed),
+        None => generator(
+            config.noise,
+            config.perlin_octaves,
+            config.perlin_persistence,
+            config.perlin_tile_period,
+            seed,
+        ),
+    }
+}
+
+/// Builds a fractal noise generator. A non-zero `tile_period` makes Perlin noise repeat with that
+/// period, and is ignored by the other generators. Panics if the period does not suit
+/// [`PerlinNoise::tileable`], which [`crate::world_config::WorldConfig::validate`] rules out for
+/// the terrain
+pub fn generator(
+    kind: NoiseKind,
+    octaves: usize,
+    persistence: f32,
+    tile_period: u32,
+    seed: u64,
+) -> Box<dyn Noise2D> {
+    let fractal = |noise: Box<dyn Noise2D>| -> Box<dyn Noise2D> {
+        Box::new(Fractal::new(noise, octaves, persistence))
+    };
+
+    match kind {
+        NoiseKind::Perlin if tile_period > 0 => Box::new(
+            PerlinNoise::tileable(tile_period, octaves, persistence, seed)
+                .unwrap_or_else(|error| panic!("{error}")),
+        ),
+        NoiseKind::Perlin => Box::new(PerlinNoise::new(octaves, persistence, seed)),
+        NoiseKind::Simplex => fractal(Box::new(SimplexNoise::new(seed))),
+        NoiseKind::OpenSimplex2 => fractal(Box::new(OpenSimplex2Noise::new(seed))),
+        NoiseKind::WorleyF1 => fractal(Box::new(WorleyNoise::new(seed, WorleyReturn::F1))),
+        NoiseKind::WorleyF2 => fractal(Box::new(WorleyNoise::new(seed, WorleyReturn::F2))),
+        NoiseKind::WorleyF2MinusF1 => {
+            fractal(Box::new(WorleyNoise::new(seed, WorleyReturn::F2MinusF1)))
+        }
+        NoiseKind::Value => fractal(Box::new(ValueNoise::new(seed))),
+    }
+}
+
+/// Hashes a lattice point into 64 random bits, so lattice based noise needs no precomputed
+/// tables and extends infinitely in every direction
+pub fn lattice_hash(seed: u64, x: i32, y: i32) -> u64 {
+    let hash = split_mix(seed ^ (x as u32 as u64).wrapping_mul(0x9E37_79B9_7F4A_7C15));
+    split_mix(hash ^ (y as u32 as u64).wrapping_mul(0xC2B2_AE3D_27D4_EB4F))
+}
+
+/// Maps a hash to a float in `[0, 1)`
+pub fn hash_to_unit(hash: u64) -> f32 {
+    (hash >> 40) as f32 / (1_u64 << 24) as f32
+}
+
+fn split_mix(mut value: u64) -> u64 {
+    value = value.wrapping_add(0x9E37_79B9_7F4A_7C15);
+    value = (value ^ (value >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+    value = (value ^ (value >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+    value ^ (value >> 31)
+}
@@ -0,0 +1,535 @@
+use crate::{
+    noise::{self, Noise2D},
+    perlin_noise::PerlinNoise,
+    world_config::NoiseNode,
+};
+
+#[derive(Debug, Clone)]
+/// Scales the input coordinates, so a generator can be made coarser or finer inside a graph
+pub struct Frequency<N> {
+    pub source: N,
+    pub frequency: f32,
+}
+
+impl<N: Noise2D> Noise2D for Frequency<N> {
+    fn sample(&self, x: f32, y: f32) -> f32 {
+        self.source.sample(x * self.frequency, y * self.frequency)
+    }
+}
+
+#[derive(Debug, Clone)]
+/// Musgrave's ridged multifractal. Every octave folds the noise around zero into sharp ridges, and
+/// is weighted by the octave before it so that detail gathers along the ridge lines, like eroded
+/// mountain ranges. The output is remapped to roughly `[-1, 1]`
+pub struct Ridged<N> {
+    pub source: N,
+    pub octaves: usize,
+    /// Strength of the feedback from one octave to the next
+    pub gain: f32,
+}
+
+impl<N: Noise2D> Noise2D for Ridged<N> {
+    fn sample(&self, x: f32, y: f32) -> f32 {
+        let mut weight = 1.0;
+        let mut value = 0.0;
+        let mut total_amplitude = 0.0;
+
+        for octave in 0..self.octaves {
+            let frequency = 2.0_f32.powi(octave as i32);
+            let amplitude = frequency.recip();
+
+            let ridge = 1.0 - self.source.sample(x * frequency, y * frequency).abs();
+            let signal = ridge * ridge * weight;
+            weight = (signal * self.gain).clamp(0.0, 1.0);
+
+            value += signal * amplitude;
+            total_amplitude += amplitude;
+        }
+
+        value / total_amplitude * 2.0 - 1.0
+    }
+}
+
+#[derive(Debug, Clone)]
+/// Sums octaves of the absolute value of the noise, giving rounded lumps like clouds or rolling
+/// hills. Normalized to `[-1, 1]`
+pub struct Billow<N> {
+    pub source: N,
+    pub octaves: usize,
+    pub persistence: f32,
+}
+
+impl<N: Noise2D> Noise2D for Billow<N> {
+    fn sample(&self, x: f32, y: f32) -> f32 {
+        let (value, total_amplitude) = octave_sum(self.octaves, self.persistence, |frequency| {
+            self.source.sample(x * frequency, y * frequency).abs() * 2.0 - 1.0
+        });
+        value / total_amplitude
+    }
+}
+
+#[derive(Debug, Clone)]
+/// Perlin's turbulence, the sum of octaves of the absolute value of the noise. Unlike
+/// [`Billow`] it is not recentred per octave, so it stays dark with sharp creases. Normalized to
+/// `[-1, 1]`
+pub struct Turbulence<N> {
+    pub source: N,
+    pub octaves: usize,
+    pub persistence: f32,
+}
+
+impl<N: Noise2D> Noise2D for Turbulence<N> {
+    fn sample(&self, x: f32, y: f32) -> f32 {
+        let (value, total_amplitude) = octave_sum(self.octaves, self.persistence, |frequency| {
+            self.source.sample(x * frequency, y * frequency).abs()
+        });
+        value / total_amplitude * 2.0 - 1.0
+    }
+}
+
+/// Sums `octave(frequency)` over octaves starting at frequency 1, doubling the frequency and
+/// scaling the amplitude by `persistence` each time. Returns the sum and the total amplitude
+fn octave_sum(octaves: usize, persistence: f32, octave: impl Fn(f32) -> f32) -> (f32, f32) {
+    (0..octaves).fold((0.0, 0.0), |(value, total_amplitude), i| {
+        let amplitude = persistence.powi(i as i32);
+        (
+            value + octave(2.0_f32.powi(i as i32)) * amplitude,
+            total_amplitude + amplitude,
+        )
+    })
+}
+
+#[derive(Debug, Clone)]
+/// Offsets the coordinates of `source` by the value of `warp`, bending its features around. The
+/// vertical offset samples `warp` at a shifted position so the two directions are independent
+pub struct DomainWarp<N, W> {
+    pub source: N,
+    pub warp: W,
+    /// Largest offset, in noise units
+    pub strength: f32,
+}
+
+impl<N, W> DomainWarp<N, W> {
+    const SECOND_CHANNEL_OFFSET: (f32, f32) = (5.2, 1.3);
+}
+
+impl<N: Noise2D, W: Noise2D> Noise2D for DomainWarp<N, W> {
+    fn sample(&self, x: f32, y: f32) -> f32 {
+        let (offset_x, offset_y) = Self::SECOND_CHANNEL_OFFSET;
+        let warp_x = self.warp.sample(x, y);
+        let warp_y = self.warp.sample(x + offset_x, y + offset_y);
+        self.source
+            .sample(x + warp_x * self.strength, y + warp_y * self.strength)
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Add<A, B> {
+    pub a: A,
+    pub b: B,
+}
+
+impl<A: Noise2D, B: Noise2D> Noise2D for Add<A, B> {
+    fn sample(&self, x: f32, y: f32) -> f32 {
+        self.a.sample(x, y) + self.b.sample(x, y)
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Multiply<A, B> {
+    pub a: A,
+    pub b: B,
+}
+
+impl<A: Noise2D, B: Noise2D> Noise2D for Multiply<A, B> {
+    fn sample(&self, x: f32, y: f32) -> f32 {
+        self.a.sample(x, y) * self.b.sample(x, y)
+    }
+}
+
+#[derive(Debug, Clone)]
+/// Picks `low` where `control` is below `threshold` and `high` above it, blending the two
+/// smoothly within `falloff` of the threshold
+pub struct Select<C, L, H> {
+    pub control: C,
+    pub low: L,
+    pub high: H,
+    pub threshold: f32,
+    pub falloff: f32,
+}
+
+impl<C: Noise2D, L: Noise2D, H: Noise2D> Noise2D for Select<C, L, H> {
+    fn sample(&self, x: f32, y: f32) -> f32 {
+        let control = self.control.sample(x, y);
+        if control <= self.threshold - self.falloff {
+            return self.low.sample(x, y);
+        }
+        if control >= self.threshold + self.falloff {
+            return self.high.sample(x, y);
+        }
+
+        let t = (control - (self.threshold - self.falloff)) / (2.0 * self.falloff);
+        PerlinNoise::lerp(
+            self.low.sample(x, y),
+            self.high.sample(x, y),
+            t * t * (3.0 - 2.0 * t),
+        )
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Clamp<N> {
+    pub source: N,
+    pub min: f32,
+    pub max: f32,
+}
+
+impl<N: Noise2D> Noise2D for Clamp<N> {
+    fn sample(&self, x: f32, y: f32) -> f32 {
+        self.source.sample(x, y).clamp(self.min, self.max)
+    }
+}
+
+#[derive(Debug, Clone)]
+/// Remaps the value of `source` through a piecewise linear curve. `points` are `(input, output)`
+/// pairs sorted by input, and inputs outside them keep the output of the nearest end
+pub struct Curve<N> {
+    pub source: N,
+    pub points: Vec<(f32, f32)>,
+}
+
+impl<N: Noise2D> Noise2D for Curve<N> {
+    fn sample(&self, x: f32, y: f32) -> f32 {
+        let value = self.source.sample(x, y);
+        let next = self.points.partition_point(|(input, _)| *input < value);
+
+        if next == 0 {
+            return self.points[0].1;
+        }
+        if next == self.points.len() {
+            return self.points[next - 1].1;
+        }
+
+        let (start_input, start_output) = self.points[next - 1];
+        let (end_input, end_output) = self.points[next];
+        PerlinNoise::lerp(
+            start_output,
+            end_output,
+            (value - start_input) / (end_input - start_input),
+        )
+    }
+}
+
+#[derive(Debug, Clone)]
+/// `source * scale + bias`
+pub struct ScaleBias<N> {
+    pub source: N,
+    pub scale: f32,
+    pub bias: f32,
+}
+
+impl<N: Noise2D> Noise2D for ScaleBias<N> {
+    fn sample(&self, x: f32, y: f32) -> f32 {
+        self.source.sample(x, y) * self.scale + self.bias
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Constant {
+    pub value: f32,
+}
+
+impl Noise2D for Constant {
+    fn sample(&self, _x: f32, _y: f32) -> f32 {
+        self.value
+    }
+}
+
+/// Builds the noise graph described by a config node. Every generator in the graph gets its own
+/// seed derived from `seed` and its `seed_offset`
+pub fn build(node: &NoiseNode, seed: u64) -> Box<dyn Noise2D> {
+    let child = |node: &NoiseNode| build(node, seed);
+
+    match node {
+        NoiseNode::Generator {
+            noise,
+            frequency,
+            octaves,
+            persistence,
+            seed_offset,
+        } => Box::new(Frequency {
+            source: noise::generator(
+                *noise,
+                *octaves,
+                *persistence,
+                0,
+                seed.wrapping_add(*seed_offset),
+            ),
+            frequency: *frequency,
+        }),
+        NoiseNode::Ridged {
+            source,
+            octaves,
+            gain,
+        } => Box::new(Ridged {
+            source: child(source),
+            octaves: *octaves,
+            gain: *gain,
+        }),
+        NoiseNode::Billow {
+            source,
+            octaves,
+            persistence,
+        } => Box::new(Billow {
+            source: child(source),
+            octaves: *octaves,
+            persistence: *persistence,
+        }),
+        NoiseNode::Turbulence {
+            source,
+            octaves,
+            persistence,
+        } => Box::new(Turbulence {
+            source: child(source),
+            octaves: *octaves,
+            persistence: *persistence,
+        }),
+        NoiseNode::DomainWarp {
+            source,
+            warp,
+            strength,
+        } => Box::new(DomainWarp {
+            source: child(source),
+            warp: child(warp),
+            strength: *strength,
+        }),
+        NoiseNode::Add { a, b } => Box::new(Add {
+            a: child(a),
+            b: child(b),
+        }),
+        NoiseNode::Multiply { a, b } => Box::new(Multiply {
+            a: child(a),
+            b: child(b),
+        }),
+        NoiseNode::Select {
+            control,
+            low,
+            high,
+            threshold,
+            falloff,
+        } => Box::new(Select {
+            control: child(control),
+            low: child(low),
+            high: child(high),
+            threshold: *threshold,
+            falloff: *falloff,
+        }),
+        NoiseNode::Clamp { source, min, max } => Box::new(Clamp {
+            source: child(source),
+            min: *min,
+            max: *max,
+        }),
+        NoiseNode::Curve { source, points } => Box::new(Curve {
+            source: child(source),
+            points: points
+                .iter()
+                .map(|[input, output]| (*input, *output))
+                .collect(),
+        }),
+        NoiseNode::ScaleBias {
+            source,
+            scale,
+            bias,
+        } => Box::new(ScaleBias {
+            source: child(source),
+            scale: *scale,
+            bias: *bias,
+        }),
+        NoiseNode::Constant { value } => Box::new(Constant { value: *value }),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::world_config::NoiseKind;
+
+    /// Noise equal to the x coordinate, for checking exact values
+    struct Ramp;
+
+    impl Noise2D for Ramp {
+        fn sample(&self, x: f32, _y: f32) -> f32 {
+            x
+        }
+    }
+
+    fn perlin() -> Box<dyn Noise2D> {
+        noise::generator(NoiseKind::Perlin, 1, 0.5, 0, 4)
+    }
+
+    /// Smallest and largest value of `noise` over a grid covering a few noise cells
+    fn value_range(noise: &impl Noise2D) -> (f32, f32) {
+        (0..64 * 64)
+            .map(|i| noise.sample((i % 64) as f32 * 0.13, (i / 64) as f32 * 0.13))
+            .fold((f32::INFINITY, f32::NEG_INFINITY), |(min, max), value| {
+                (min.min(value), max.max(value))
+            })
+    }
+
+    fn assert_within_unit_range(noise: &impl Noise2D) {
+        let (min, max) = value_range(noise);
+        assert!(
+            min >= -1.0 - 1e-4 && max <= 1.0 + 1e-4,
+            "range [{min}, {max}]"
+        );
+        assert!(max - min > 0.1, "range [{min}, {max}] is flat");
+    }
+
+    #[test]
+    fn ridged_stays_in_range_and_peaks_at_zero_crossings() {
+        assert_within_unit_range(&Ridged {
+            source: perlin(),
+            octaves: 4,
+            gain: 2.0,
+        });
+
+        let ridge = Ridged {
+            source: Constant { value: 0.0 },
+            octaves: 3,
+            gain: 2.0,
+        };
+        assert_eq!(ridge.sample(0.3, 0.7), 1.0);
+    }
+
+    #[test]
+    fn billow_stays_in_range() {
+        assert_within_unit_range(&Billow {
+            source: perlin(),
+            octaves: 4,
+            persistence: 0.5,
+        });
+
+        // The absolute value of -0.5 is recentred to zero in every octave
+        let billow = Billow {
+            source: Constant { value: -0.5 },
+            octaves: 3,
+            persistence: 0.5,
+        };
+        assert_eq!(billow.sample(1.0, 2.0), 0.0);
+    }
+
+    #[test]
+    fn turbulence_stays_in_range() {
+        assert_within_unit_range(&Turbulence {
+            source: perlin(),
+            octaves: 4,
+            persistence: 0.5,
+        });
+
+        let turbulence = Turbulence {
+            source: Constant { value: -1.0 },
+            octaves: 3,
+            persistence: 0.5,
+        };
+        assert_eq!(turbulence.sample(1.0, 2.0), 1.0);
+    }
+
+    #[test]
+    fn domain_warp_offsets_the_coordinates() {
+        let warp = DomainWarp {
+            source: Ramp,
+            warp: Constant { value: 0.5 },
+            strength: 2.0,
+        };
+        assert_eq!(warp.sample(3.0, 0.0), 4.0);
+    }
+
+    #[test]
+    fn combiners_give_known_values() {
+        let add = Add {
+            a: Constant { value: 0.25 },
+            b: Ramp,
+        };
+        assert_eq!(add.sample(0.5, 0.0), 0.75);
+
+        let multiply = Multiply {
+            a: Constant { value: 0.25 },
+            b: Ramp,
+        };
+        assert_eq!(multiply.sample(0.5, 0.0), 0.125);
+
+        let clamp = Clamp {
+            source: Ramp,
+            min: -0.5,
+            max: 0.5,
+        };
+        assert_eq!(clamp.sample(-2.0, 0.0), -0.5);
+        assert_eq!(clamp.sample(0.25, 0.0), 0.25);
+        assert_eq!(clamp.sample(2.0, 0.0), 0.5);
+
+        let scale_bias = ScaleBias {
+            source: Ramp,
+            scale: 2.0,
+            bias: 1.0,
+        };
+        assert_eq!(scale_bias.sample(3.0, 0.0), 7.0);
+    }
+
+    #[test]
+    fn select_blends_within_the_falloff() {
+        let select = Select {
+            control: Ramp,
+            low: Constant { value: -1.0 },
+            high: Constant { value: 1.0 },
+            threshold: 0.0,
+            falloff: 0.5,
+        };
+
+        assert_eq!(select.sample(-1.0, 0.0), -1.0);
+        assert_eq!(select.sample(0.0, 0.0), 0.0);
+        assert_eq!(select.sample(1.0, 0.0), 1.0);
+        let blended = select.sample(0.25, 0.0);
+        assert!(blended > 0.0 && blended < 1.0);
+    }
+
+    #[test]
+    fn curve_interpolates_between_points() {
+        let curve = Curve {
+            source: Ramp,
+            points: vec![(0.0, 0.0), (1.0, 10.0), (2.0, 0.0)],
+        };
+
+        assert_eq!(curve.sample(-1.0, 0.0), 0.0);
+        assert_eq!(curve.sample(0.5, 0.0), 5.0);
+        assert_eq!(curve.sample(1.5, 0.0), 5.0);
+        assert_eq!(curve.sample(3.0, 0.0), 0.0);
+    }
+
+    #[test]
+    fn scale_bias_defaults_to_the_identity() {
+        let node: NoiseNode = toml::from_str(
+            r#"
+            type = "scale_bias"
+            source = { type = "constant", value = 0.75 }
+            "#,
+        )
+        .unwrap();
+
+        assert_eq!(build(&node, 0).sample(0.0, 0.0), 0.75);
+    }
+
+    #[test]
+    fn generator_frequency_scales_the_coordinates() {
+        let node = |frequency| NoiseNode::Generator {
+            noise: NoiseKind::Simplex,
+            frequency,
+            octaves: 1,
+            persistence: 0.5,
+            seed_offset: 0,
+        };
+        let coarse = build(&node(1.0), 9);
+        let fine = build(&node(4.0), 9);
+
+        assert_eq!(fine.sample(0.5, 0.25), coarse.sample(2.0, 1.0));
+    }
+}
@@ -0,0 +1,208 @@
+use gamezap::texture::Texture;
+use nalgebra::Vector2;
+use rand::{Rng, SeedableRng};
+use rand_chacha::ChaCha8Rng;
+use rayon::prelude::*;
+
+use crate::{
+    heightmap::{Heightmap, NEIGHBOUR_OFFSETS},
+    world_config::{OceanConfig, OceanFalloff},
+};
+
+/// Sinks the terrain towards the ocean so that the map becomes an island or a coast. Heights are
+/// scaled down to zero over the outer `1 - falloff_start` of the distance from the center for
+/// radial falloff, or from the far edge towards one map edge picked from the seed for edge falloff.
+pub fn apply_falloff(heightmap: &mut Heightmap, config: &OceanConfig, seed: u64) {
+    if config.falloff == OceanFalloff::None {
+        return;
+    }
+
+    let resolution = heightmap.resolution();
+    let max_cell = (resolution - 1).max(1) as f32;
+    let coast_side = ChaCha8Rng::seed_from_u64(seed).gen_range(0..4);
+
+    let distance = |x: u32, y: u32| -> f32 {
+        let position = Vector2::new(x as f32, y as f32) / max_cell;
+        match config.falloff {
+            OceanFalloff::None => 0.0,
+            OceanFalloff::Radial => ((position - Vector2::new(0.5, 0.5)).norm() * 2.0).min(1.0),
+            OceanFalloff::Edge => match coast_side {
+                0 => 1.0 - position.y,
+                1 => position.y,
+                2 => 1.0 - position.x,
+                _ => position.x,
+            },
+        }
+    };
+
+    heightmap
+        .heights_mut()
+        .par_iter_mut()
+        .enumerate()
+        .for_each(|(i, height)| {
+            let x = i as u32 % resolution;
+            let y = i as u32 / resolution;
+            let t = ((distance(x, y) - config.falloff_start) / (1.0 - config.falloff_start))
+                .clamp(0.0, 1.0);
+            *height *= 1.0 - t * t * (3.0 - 2.0 * t);
+        });
+}
+
+#[derive(Debug, Clone)]
+/// Cells below sea level that are connected to the map edge. Basins below sea level further
+/// inland are left to the lakes.
+pub struct OceanMap {
+    sea_level: f32,
+    /// One for every ocean cell, zero on land
+    mask: Heightmap,
+}
+
+impl OceanMap {
+    pub fn new(heightmap: &Heightmap, sea_level: f32) -> Self {
+        let resolution = heightmap.resolution() as i32;
+        let heights = heightmap.heights();
+        let mut mask = Heightmap::new(heightmap.resolution(), heightmap.world_size());
+
+        let mut stack = Vec::new();
+        for y in 0..resolution {
+            for x in 0..resolution {
+                let index = (y * resolution + x) as usize;
+                if (x == 0 || y == 0 || x == resolution - 1 || y == resolution - 1)
+                    && heights[index] < sea_level
+                {
+                    mask.heights_mut()[index] = 1.0;
+                    stack.push(index);
+                }
+            }
+        }
+
+        while let Some(index) = stack.pop() {
+            let x = index as i32 % resolution;
+            let y = index as i32 / resolution;
+
+            for (offset_x, offset_y) in NEIGHBOUR_OFFSETS {
+                let neighbour_x = x + offset_x;
+                let neighbour_y = y + offset_y;
+                if neighbour_x < 0
+                    || neighbour_y < 0
+                    || neighbour_x >= resolution
+                    || neighbour_y >= resolution
+                {
+                    continue;
+                }
+
+                let neighbour = (neighbour_y * resolution + neighbour_x) as usize;
+                if mask.heights()[neighbour] == 0.0 && heights[neighbour] < sea_level {
+                    mask.heights_mut()[neighbour] = 1.0;
+                    stack.push(neighbour);
+                }
+            }
+        }
+
+        Self { sea_level, mask }
+    }
+
+    pub fn sea_level(&self) -> f32 {
+        self.sea_level
+    }
+
+    pub fn is_ocean(&self, index: usize) -> bool {
+        self.mask.heights()[index] > 0.0
+    }
+
+    /// Whether any cell lies in the ocean
+    pub fn has_ocean(&self) -> bool {
+        self.mask.heights().iter().any(|cell| *cell > 0.0)
+    }
+
+    /// One for every ocean cell, zero on land
+    pub fn mask(&self) -> &Heightmap {
+        &self.mask
+    }
+
+    /// Indices of the ocean cells that border land
+    pub fn coast_cells(&self) -> Vec<usize> {
+        let resolution = self.mask.resolution() as i32;
+        (0..self.mask.heights().len())
+            .filter(|i| {
+                if !self.is_ocean(*i) {
+                    return false;
+                }
+                let x = *i as i32 % resolution;
+                let y = *i as i32 / resolution;
+                NEIGHBOUR_OFFSETS.iter().any(|(offset_x, offset_y)| {
+                    let neighbour_x = x + offset_x;
+                    let neighbour_y = y + offset_y;
+                    neighbour_x >= 0
+                        && neighbour_y >= 0
+                        && neighbour_x < resolution
+                        && neighbour_y < resolution
+                        && !self.is_ocean((neighbour_y * resolution + neighbour_x) as usize)
+                })
+            })
+            .collect()
+    }
+
+    pub fn create_texture(&self, device: &wgpu::Device, queue: &wgpu::Queue) -> Texture {
+        self.mask.create_texture(device, queue, "Ocean mask")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn only_low_cells_connected_to_the_edge_are_ocean() {
+        // A channel below sea level runs in from the left edge, and a pit at (5, 5) is below sea
+        // level but walled in by land
+        let heightmap = Heightmap::from_fn(8, 6.0, |x, y| match (x, y) {
+            (0..=3, 2) => -0.5,
+            (5, 5) => -1.0,
+            _ => 0.5,
+        });
+        let ocean = OceanMap::new(&heightmap, 0.0);
+
+        for y in 0..8 {
+            for x in 0..8 {
+                let index = (y * 8 + x) as usize;
+                assert_eq!(ocean.is_ocean(index), x <= 3 && y == 2);
+            }
+        }
+        assert!(ocean.has_ocean());
+        assert!(!ocean.is_ocean(5 * 8 + 5));
+        assert!(!OceanMap::new(&heightmap, -2.0).has_ocean());
+    }
+
+    #[test]
+    fn falloff_sinks_the_edge_to_zero() {
+        for falloff in [OceanFalloff::Radial, OceanFalloff::Edge] {
+            let config = OceanConfig {
+                falloff,
+                falloff_start: 0.5,
+                ..Default::default()
+            };
+            for seed in 0..4 {
+                let mut heightmap = Heightmap::from_fn(9, 7.0, |_, _| 1.0);
+                apply_falloff(&mut heightmap, &config, seed);
+
+                // The middle is untouched and at least one full edge is sunk to zero
+                assert_eq!(heightmap.get(4, 4), 1.0);
+                let edges = [
+                    (0..9).map(|i| heightmap.get(i, 0)).collect::<Vec<_>>(),
+                    (0..9).map(|i| heightmap.get(i, 8)).collect(),
+                    (0..9).map(|i| heightmap.get(0, i)).collect(),
+                    (0..9).map(|i| heightmap.get(8, i)).collect(),
+                ];
+                let sunk = edges
+                    .iter()
+                    .filter(|edge| edge.iter().all(|height| *height == 0.0))
+                    .count();
+                match falloff {
+                    OceanFalloff::Radial => assert_eq!(sunk, 4),
+                    _ => assert_eq!(sunk, 1),
+                }
+            }
+        }
+    }
+}
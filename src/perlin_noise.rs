@@ -1,45 +1,89 @@
+use std::{f32::consts::TAU, fmt::Display};
+
 use nalgebra::{ComplexField, Vector2};
-use rand::{Rng, SeedableRng};
-use rand_chacha::ChaCha8Rng;
 
-#[derive(Debug)]
+use crate::noise::{hash_to_unit, lattice_hash, Noise2D};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+/// A tile period that would leave some octave with a fraction of a lattice cell per tile
+pub struct TilePeriodError {
+    pub period: u32,
+    /// The period has to be a positive multiple of this
+    pub coarsest_scale: u32,
+}
+
+impl Display for TilePeriodError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "tile period {} is not a positive multiple of {}",
+            self.period, self.coarsest_scale
+        )
+    }
+}
+
+impl std::error::Error for TilePeriodError {}
+
+#[derive(Debug, Clone)]
+/// Fractal Perlin noise. Gradients are hashed from the lattice coordinates, so the noise can be
+/// evaluated anywhere, including negative coordinates, without storing any grids
 pub struct PerlinNoise {
-    grids: Vec<Vec<Vec<Vector2<f32>>>>,
+    seed: u64,
     octaves: usize,
     persistence: f32,
+    /// Distance after which the fractal sum repeats along both axes, if it tiles
+    period: Option<u32>,
 }
 
 impl PerlinNoise {
-    pub fn new(size: usize, octaves: usize, persistence: f32, seed: u64) -> Self {
+    pub fn new(octaves: usize, persistence: f32, seed: u64) -> Self {
         Self {
-            grids: (0..octaves)
-                .map(|i| Self::generate_grid(size, seed + i as u64))
-                .collect(),
+            seed,
             octaves,
             persistence,
+            period: None,
         }
     }
 
-    fn generate_grid(size: usize, seed: u64) -> Vec<Vec<Vector2<f32>>> {
-        let mut rng = ChaCha8Rng::seed_from_u64(seed);
+    /// Noise that repeats every `period` units along both axes. Every octave needs a whole number
+    /// of lattice cells per tile, so `period` has to be a positive multiple of `2^(octaves - 1)`
+    pub fn tileable(
+        period: u32,
+        octaves: usize,
+        persistence: f32,
+        seed: u64,
+    ) -> Result<Self, TilePeriodError> {
+        let coarsest_scale = Self::coarsest_scale(octaves);
+        if period == 0 || !period.is_multiple_of(coarsest_scale) {
+            return Err(TilePeriodError {
+                period,
+                coarsest_scale,
+            });
+        }
 
-        let mut grid = vec![vec![Vector2::zeros(); size + 2]; size + 2];
+        Ok(Self {
+            period: Some(period),
+            ..Self::new(octaves, persistence, seed)
+        })
+    }
 
-        for row in &mut grid {
-            for vector in row.iter_mut() {
-                let comp_x = rng.gen_range::<f32, _>(-5.0..=5.0);
-                let comp_y = rng.gen_range::<f32, _>(-5.0..=5.0);
-                *vector = Vector2::new(comp_x, comp_y).normalize();
-            }
-            let first_vector = row[0];
-            *row.last_mut().unwrap() = first_vector;
-        }
+    /// Size of a lattice cell of the first, coarsest octave of [`PerlinNoise::reverse_octave_evaluate`]
+    pub fn coarsest_scale(octaves: usize) -> u32 {
+        1 << octaves.saturating_sub(1)
+    }
 
-        for i in 0..(size + 2) {
-            grid[size + 1][i] = grid[0][i];
-        }
+    pub fn period(&self) -> Option<u32> {
+        self.period
+    }
 
-        grid
+    /// Unit gradient of a lattice point, wrapped around the tile period of the octave
+    fn gradient(&self, x: i32, y: i32, octave: usize, octave_period: Option<i32>) -> Vector2<f32> {
+        let (x, y) = match octave_period {
+            Some(period) => (x.rem_euclid(period), y.rem_euclid(period)),
+            None => (x, y),
+        };
+        let angle = hash_to_unit(lattice_hash(self.seed.wrapping_add(octave as u64), x, y)) * TAU;
+        Vector2::new(angle.cos(), angle.sin())
     }
 
     fn fade(t: f32) -> f32 {
@@ -56,13 +100,13 @@ impl PerlinNoise {
         a + (b - a) * t
     }
 
-    /// Calculate the perlin noise value at a given `(x, y)` coordinate. The greatest `x` or `y`
-    /// values should not exceed `self.size`
-    pub fn evaluate(&self, x: f32, y: f32, octave: usize) -> f32 {
+    /// Calculate the perlin noise value at a given `(x, y)` coordinate, in lattice cells of the
+    /// octave. `octave_period` wraps the lattice for tileable noise
+    pub fn evaluate(&self, x: f32, y: f32, octave: usize, octave_period: Option<i32>) -> f32 {
         let position_vector = Vector2::new(x, y);
 
-        let x_floor = x.floor() as usize;
-        let y_floor = y.floor() as usize;
+        let x_floor = x.floor() as i32;
+        let y_floor = y.floor() as i32;
         let x_ceil = x_floor + 1;
         let y_ceil = y_floor + 1;
 
@@ -76,35 +120,34 @@ impl PerlinNoise {
         let bottom_left_distance = position_vector - bottom_left_vector;
         let bottom_right_distance = position_vector - bottom_right_vector;
 
-        let top_left_perlin = self.grids[octave][y_floor][x_floor];
-        let top_right_perlin = self.grids[octave][y_floor][x_ceil];
-        let bottom_left_perlin = self.grids[octave][y_ceil][x_floor];
-        let bottom_right_perlin = self.grids[octave][y_ceil][x_ceil];
+        let top_left_perlin = self.gradient(x_floor, y_floor, octave, octave_period);
+        let top_right_perlin = self.gradient(x_ceil, y_floor, octave, octave_period);
+        let bottom_left_perlin = self.gradient(x_floor, y_ceil, octave, octave_period);
+        let bottom_right_perlin = self.gradient(x_ceil, y_ceil, octave, octave_period);
 
         let top_left_displacement = top_left_perlin.dot(&top_left_distance);
         let top_right_displacement = top_right_perlin.dot(&top_right_distance);
         let bottom_left_displacement = bottom_left_perlin.dot(&bottom_left_distance);
         let bottom_right_displacement = bottom_right_perlin.dot(&bottom_right_distance);
 
-        let top_lerp = Self::lerp(
-            top_left_displacement,
-            top_right_displacement,
-            Self::fade(x % 1.0),
-        );
-        let bottom_lerp = Self::lerp(
-            bottom_left_displacement,
-            bottom_right_displacement,
-            Self::fade(x % 1.0),
-        );
+        let fade_x = Self::fade(top_left_distance.x);
+        let top_lerp = Self::lerp(top_left_displacement, top_right_displacement, fade_x);
+        let bottom_lerp = Self::lerp(bottom_left_displacement, bottom_right_displacement, fade_x);
 
-        Self::lerp(top_lerp, bottom_lerp, Self::fade(y % 1.0))
+        Self::lerp(top_lerp, bottom_lerp, Self::fade(top_left_distance.y))
     }
 
     pub fn octave_evaluate(&self, x: f32, y: f32) -> f32 {
         (0..self.octaves)
             .map(|i| {
+                let octave_period = self.period.map(|period| (period << i) as i32);
                 self.persistence.powi(i as i32)
-                    * self.evaluate(2.0.powi(i as i32) * x, 2.0.powi(i as i32) * y, i)
+                    * self.evaluate(
+                        2.0.powi(i as i32) * x,
+                        2.0.powi(i as i32) * y,
+                        i,
+                        octave_period,
+                    )
             })
             .sum()
     }
@@ -115,10 +158,52 @@ impl PerlinNoise {
         (0..self.octaves)
             .map(|old_i| {
                 let i = (self.octaves - 1 - old_i) as i32;
+                let octave_period = self.period.map(|period| (period >> i) as i32);
 
-                self.evaluate(x / (2.0.powi(i)), 0.5.powi(i) * y, old_i)
+                self.evaluate(x / (2.0.powi(i)), 0.5.powi(i) * y, old_i, octave_period)
                     * self.persistence.powi(old_i as i32)
             })
             .sum()
     }
 }
+
+impl Noise2D for PerlinNoise {
+    /// The fractal noise the terrain has always used
+    fn sample(&self, x: f32, y: f32) -> f32 {
+        self.reverse_octave_evaluate(x, y)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn tileable_noise_repeats_every_period() {
+        let perlin = PerlinNoise::tileable(16, 5, 0.5, 7).unwrap();
+        for (x, y) in [(0.0, 0.0), (0.3, 5.7), (-3.2, 11.9), (15.5, -0.25)] {
+            let value = perlin.sample(x, y);
+            assert!((perlin.sample(x + 16.0, y) - value).abs() < 1e-5);
+            assert!((perlin.sample(x, y - 32.0) - value).abs() < 1e-5);
+        }
+    }
+
+    #[test]
+    fn tile_period_must_fit_the_coarsest_octave() {
+        assert_eq!(
+            PerlinNoise::tileable(30, 5, 0.5, 0).unwrap_err(),
+            TilePeriodError {
+                period: 30,
+                coarsest_scale: 16,
+            }
+        );
+        assert!(PerlinNoise::tileable(0, 1, 0.5, 0).is_err());
+        assert!(PerlinNoise::tileable(3, 1, 0.5, 0).is_ok());
+    }
+
+    #[test]
+    fn octave_seeds_wrap_at_the_largest_seed() {
+        let perlin = PerlinNoise::new(4, 0.5, u64::MAX);
+        assert!(perlin.sample(3.7, 1.2).is_finite());
+    }
+}
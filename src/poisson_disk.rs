@@ -0,0 +1,184 @@
+use nalgebra::Vector2;
+use rand::{seq::SliceRandom, Rng};
+
+#[derive(Debug, Clone, Copy)]
+/// Bridson's Poisson-disk sampler. Scatters points over a `[0, width) x [0, height)` rectangle
+/// so that no two are closer than `min_distance`, and keeps adding points until no more fit
+pub struct PoissonDiskSampler {
+    width: f32,
+    height: f32,
+    min_distance: f32,
+    /// Candidates tried around every point before it stops spawning new ones
+    attempts: usize,
+}
+
+impl PoissonDiskSampler {
+    /// Number of candidates Bridson suggests trying around every point
+    pub const DEFAULT_ATTEMPTS: usize = 30;
+
+    pub fn new(width: f32, height: f32, min_distance: f32, attempts: usize) -> Self {
+        assert!(min_distance > 0.0, "the minimum distance must be positive");
+        Self {
+            width,
+            height,
+            min_distance,
+            attempts,
+        }
+    }
+
+    /// Samples points where `allowed` holds. New points are tried in the ring between one and two
+    /// times `min_distance` around an existing point, and whenever the points run out of room
+    /// sampling starts over from another allowed pixel, so regions of the allowed area that are
+    /// far apart are all filled
+    pub fn sample(
+        &self,
+        rng: &mut impl Rng,
+        allowed: impl Fn(Vector2<f32>) -> bool,
+    ) -> Vec<Vector2<f32>> {
+        let cell_size = self.min_distance / std::f32::consts::SQRT_2;
+        let grid_width = (self.width / cell_size).ceil().max(1.0) as usize;
+        let grid_height = (self.height / cell_size).ceil().max(1.0) as usize;
+        // Every grid cell is small enough to hold at most one point
+        let mut grid = vec![None; grid_width * grid_height];
+        let grid_index = |point: Vector2<f32>| {
+            let x = ((point.x / cell_size) as usize).min(grid_width - 1);
+            let y = ((point.y / cell_size) as usize).min(grid_height - 1);
+            (x, y)
+        };
+
+        let mut points: Vec<Vector2<f32>> = Vec::new();
+        let fits = |point: Vector2<f32>, points: &[Vector2<f32>], grid: &[Option<usize>]| {
+            if point.x < 0.0 || point.y < 0.0 || point.x >= self.width || point.y >= self.height {
+                return false;
+            }
+            let (x, y) = grid_index(point);
+            // Points closer than `min_distance` are at most two cells away
+            for neighbour_y in y.saturating_sub(2)..(y + 3).min(grid_height) {
+                for neighbour_x in x.saturating_sub(2)..(x + 3).min(grid_width) {
+                    if let Some(other) = grid[neighbour_y * grid_width + neighbour_x] {
+                        let other: Vector2<f32> = points[other];
+                        if (other - point).norm_squared() < self.min_distance * self.min_distance {
+                            return false;
+                        }
+                    }
+                }
+            }
+            allowed(point)
+        };
+
+        // Pixels to restart from, in random order
+        let mut starts = (0..self.height as u32)
+            .flat_map(|y| (0..self.width as u32).map(move |x| Vector2::new(x as f32, y as f32)))
+            .collect::<Vec<_>>();
+        starts.shuffle(rng);
+
+        let mut active = Vec::new();
+        for start in starts {
+            if !fits(start, &points, &grid) {
+                continue;
+            }
+            let (x, y) = grid_index(start);
+            grid[y * grid_width + x] = Some(points.len());
+            active.push(points.len());
+            points.push(start);
+
+            while !active.is_empty() {
+                let active_index = rng.gen_range(0..active.len());
+                let center = points[active[active_index]];
+
+                let candidate = (0..self.attempts)
+                    .map(|_| {
+                        let angle = rng.gen_range(0.0..std::f32::consts::TAU);
+                        let distance = rng.gen_range(self.min_distance..2.0 * self.min_distance);
+                        center + Vector2::new(angle.cos(), angle.sin()) * distance
+                    })
+                    .find(|candidate| fits(*candidate, &points, &grid));
+
+                match candidate {
+                    Some(candidate) => {
+                        let (x, y) = grid_index(candidate);
+                        grid[y * grid_width + x] = Some(points.len());
+                        active.push(points.len());
+                        points.push(candidate);
+                    }
+                    None => {
+                        active.swap_remove(active_index);
+                    }
+                }
+            }
+        }
+
+        points
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rand::SeedableRng;
+    use rand_chacha::ChaCha8Rng;
+
+    use super::*;
+
+    fn min_spacing(points: &[Vector2<f32>]) -> f32 {
+        points
+            .iter()
+            .enumerate()
+            .flat_map(|(i, a)| points[i + 1..].iter().map(move |b| (a - b).norm()))
+            .fold(f32::INFINITY, f32::min)
+    }
+
+    #[test]
+    fn points_keep_their_distance() {
+        for seed in 0..5 {
+            let mut rng = ChaCha8Rng::seed_from_u64(seed);
+            let points =
+                PoissonDiskSampler::new(100.0, 60.0, 7.5, PoissonDiskSampler::DEFAULT_ATTEMPTS)
+                    .sample(&mut rng, |_| true);
+
+            assert!(points.len() > 20);
+            assert!(min_spacing(&points) >= 7.5);
+            assert!(points
+                .iter()
+                .all(|point| (0.0..100.0).contains(&point.x) && (0.0..60.0).contains(&point.y)));
+        }
+    }
+
+    #[test]
+    fn points_fill_the_area() {
+        let mut rng = ChaCha8Rng::seed_from_u64(3);
+        let points = PoissonDiskSampler::new(50.0, 50.0, 5.0, PoissonDiskSampler::DEFAULT_ATTEMPTS)
+            .sample(&mut rng, |_| true);
+
+        // Every pixel is tried as a start, so none is left as far as the spacing from a point
+        for y in 0..50 {
+            for x in 0..50 {
+                let pixel = Vector2::new(x as f32, y as f32);
+                assert!(points.iter().any(|point| (point - pixel).norm() < 5.0));
+            }
+        }
+    }
+
+    #[test]
+    fn points_stay_in_the_allowed_area() {
+        let mut rng = ChaCha8Rng::seed_from_u64(1);
+        // Two strips far enough apart that no ring around one reaches the other
+        let allowed = |point: Vector2<f32>| point.x < 10.0 || point.x >= 90.0;
+        let points =
+            PoissonDiskSampler::new(100.0, 100.0, 4.0, PoissonDiskSampler::DEFAULT_ATTEMPTS)
+                .sample(&mut rng, allowed);
+
+        assert!(points.iter().all(|point| allowed(*point)));
+        assert!(points.iter().any(|point| point.x < 10.0));
+        assert!(points.iter().any(|point| point.x >= 90.0));
+        assert!(min_spacing(&points) >= 4.0);
+    }
+
+    #[test]
+    fn nothing_is_sampled_without_room() {
+        let mut rng = ChaCha8Rng::seed_from_u64(0);
+        let points = PoissonDiskSampler::new(20.0, 20.0, 5.0, PoissonDiskSampler::DEFAULT_ATTEMPTS)
+            .sample(&mut rng, |_| false);
+
+        assert!(points.is_empty());
+    }
+}
@@ -0,0 +1,380 @@
+use nalgebra::Vector2;
+use rayon::prelude::*;
+
+use crate::{
+    perlin_noise::PerlinNoise,
+    resource_generator::ResourceMap,
+    world_config::{ResourceKind, ResourcesConfig},
+};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+/// Inclusive range of cells along both axes
+struct CellRect {
+    min_x: u32,
+    min_y: u32,
+    max_x: u32,
+    max_y: u32,
+}
+
+impl CellRect {
+    fn cell(x: u32, y: u32) -> Self {
+        Self {
+            min_x: x,
+            min_y: y,
+            max_x: x,
+            max_y: y,
+        }
+    }
+
+    fn union(self, other: Self) -> Self {
+        Self {
+            min_x: self.min_x.min(other.min_x),
+            min_y: self.min_y.min(other.min_y),
+            max_x: self.max_x.max(other.max_x),
+            max_y: self.max_y.max(other.max_y),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+/// The quantity of every resource left in every cell of the map. Deposits are mined with
+/// [`ResourceField::extract`] and renewable resources grow back in [`ResourceField::update`].
+/// Positions are in world units from the corner of the map, like the other generators, and
+/// changed cells are tracked so the resource textures can be rewritten piece by piece
+pub struct ResourceField {
+    /// One layer per kind, in the order of [`ResourceKind::ALL`]
+    quantities: Vec<Vec<f32>>,
+    /// Quantities the field started with, which renewable resources grow back to
+    capacities: Vec<Vec<f32>>,
+    /// Quantity of every kind that fills its texture channel
+    full_quantities: [f32; ResourceKind::COUNT],
+    regeneration: [f32; ResourceKind::COUNT],
+    resolution: u32,
+    /// Distance between neighbouring cells, which is the quad size of the terrain mesh
+    cell_size: f32,
+    /// Cells changed since the textures were last written
+    dirty: Option<CellRect>,
+}
+
+impl ResourceField {
+    /// Fills the field from the deposits of a resource map laid over the heightmap, with
+    /// [`Heightmap::cell_size`](crate::heightmap::Heightmap::cell_size) between its cells. Like
+    /// the heightmap the map has a one cell border, so cell `(1, 1)` sits on the corner of the
+    /// terrain
+    pub fn new(map: &ResourceMap, config: &ResourcesConfig, cell_size: f32) -> Self {
+        let resolution = map.texture_size();
+        let full_quantities = ResourceKind::ALL.map(|kind| config.get(kind).quantity);
+        let regeneration = ResourceKind::ALL.map(|kind| config.get(kind).regeneration);
+
+        let quantities = ResourceKind::ALL
+            .into_iter()
+            .map(|kind| {
+                map.deposits(kind)
+                    .amount_layer()
+                    .into_iter()
+                    .map(|amount| amount * full_quantities[kind.index()])
+                    .collect::<Vec<_>>()
+            })
+            .collect::<Vec<_>>();
+
+        Self {
+            capacities: quantities.clone(),
+            quantities,
+            full_quantities,
+            regeneration,
+            resolution,
+            cell_size,
+            dirty: None,
+        }
+    }
+
+    pub fn resolution(&self) -> u32 {
+        self.resolution
+    }
+
+    /// Cell nearest to a position, if the position is on the map
+    pub fn cell(&self, position: Vector2<f32>) -> Option<(u32, u32)> {
+        let x = (position.x / self.cell_size + 1.0).round();
+        let y = (position.y / self.cell_size + 1.0).round();
+        let max = (self.resolution - 1) as f32;
+        ((0.0..=max).contains(&x) && (0.0..=max).contains(&y)).then_some((x as u32, y as u32))
+    }
+
+    /// Quantity of a resource in the cell nearest to `position`, zero off the map
+    pub fn quantity_at(&self, kind: ResourceKind, position: Vector2<f32>) -> f32 {
+        self.cell(position)
+            .map(|(x, y)| self.quantities[kind.index()][(y * self.resolution + x) as usize])
+            .unwrap_or(0.0)
+    }
+
+    /// Total quantity of a resource in the cells within `radius` world units of `center`
+    pub fn quantity_within(&self, kind: ResourceKind, center: Vector2<f32>, radius: f32) -> f32 {
+        let layer = &self.quantities[kind.index()];
+        self.cells_within(center, radius)
+            .map(|(x, y)| layer[(y * self.resolution + x) as usize])
+            .sum()
+    }
+
+    /// Takes up to `amount` of a resource from the cells within `radius` world units of
+    /// `center`, from every cell in proportion to what it holds. Returns the quantity taken,
+    /// which is less than `amount` when the cells run out
+    pub fn extract(
+        &mut self,
+        kind: ResourceKind,
+        center: Vector2<f32>,
+        radius: f32,
+        amount: f32,
+    ) -> f32 {
+        let available = self.quantity_within(kind, center, radius);
+        if available <= 0.0 || amount <= 0.0 {
+            return 0.0;
+        }
+        let taken_fraction = (amount / available).min(1.0);
+
+        let cells = self.cells_within(center, radius).collect::<Vec<_>>();
+        let layer = &mut self.quantities[kind.index()];
+        for (x, y) in cells {
+            let quantity = &mut layer[(y * self.resolution + x) as usize];
+            if *quantity > 0.0 {
+                *quantity -= *quantity * taken_fraction;
+                self.dirty = Some(self.dirty.map_or(CellRect::cell(x, y), |dirty| {
+                    dirty.union(CellRect::cell(x, y))
+                }));
+            }
+        }
+
+        available * taken_fraction
+    }
+
+    /// Grows renewable resources back towards their starting quantities over `delta_seconds`
+    pub fn update(&mut self, delta_seconds: f32) {
+        let resolution = self.resolution;
+        for kind in ResourceKind::ALL {
+            let regeneration = self.regeneration[kind.index()];
+            if regeneration <= 0.0 {
+                continue;
+            }
+
+            let grown = self.quantities[kind.index()]
+                .par_iter_mut()
+                .zip(&self.capacities[kind.index()])
+                .enumerate()
+                .filter(|(_, (quantity, capacity))| **quantity < **capacity)
+                .map(|(i, (quantity, capacity))| {
+                    *quantity =
+                        (*quantity + capacity * regeneration * delta_seconds).min(*capacity);
+                    CellRect::cell(i as u32 % resolution, i as u32 / resolution)
+                })
+                .reduce_with(CellRect::union);
+
+            if let Some(grown) = grown {
+                self.dirty = Some(self.dirty.map_or(grown, |dirty| dirty.union(grown)));
+            }
+        }
+    }
+
+    /// Whether any cell changed since the textures were last written
+    pub fn is_dirty(&self) -> bool {
+        self.dirty.is_some()
+    }
+
+    /// Rewrites the changed cells of the textures made by [`ResourceMap::create_resource_maps`],
+    /// packed the same way, and only the smallest rectangle around them
+    pub fn write_textures(&mut self, queue: &wgpu::Queue, textures: &[&wgpu::Texture]) {
+        let Some(dirty) = self.dirty.take() else {
+            return;
+        };
+        let width = dirty.max_x - dirty.min_x + 1;
+        let height = dirty.max_y - dirty.min_y + 1;
+
+        let kinds = ResourceKind::ALL.chunks(ResourceMap::KINDS_PER_TEXTURE);
+        for (texture, kinds) in textures.iter().zip(kinds) {
+            let pixels = (dirty.min_y..=dirty.max_y)
+                .flat_map(|y| (dirty.min_x..=dirty.max_x).map(move |x| (x, y)))
+                .flat_map(|(x, y)| {
+                    let i = (y * self.resolution + x) as usize;
+                    let mut pixel = [0_u8; 4];
+                    for (channel, kind) in pixel.iter_mut().zip(kinds) {
+                        let amount =
+                            self.quantities[kind.index()][i] / self.full_quantities[kind.index()];
+                        *channel = PerlinNoise::lerp(0.0, 255.0, amount.clamp(0.0, 1.0)) as u8;
+                    }
+                    pixel
+                })
+                .collect::<Vec<_>>();
+
+            queue.write_texture(
+                wgpu::ImageCopyTexture {
+                    texture,
+                    mip_level: 0,
+                    origin: wgpu::Origin3d {
+                        x: dirty.min_x,
+                        y: dirty.min_y,
+                        z: 0,
+                    },
+                    aspect: wgpu::TextureAspect::All,
+                },
+                &pixels,
+                wgpu::ImageDataLayout {
+                    offset: 0,
+                    bytes_per_row: Some(4 * width),
+                    rows_per_image: Some(height),
+                },
+                wgpu::Extent3d {
+                    width,
+                    height,
+                    depth_or_array_layers: 1,
+                },
+            );
+        }
+    }
+
+    /// Cells whose centres are within `radius` world units of `center`
+    fn cells_within(
+        &self,
+        center: Vector2<f32>,
+        radius: f32,
+    ) -> impl Iterator<Item = (u32, u32)> + '_ {
+        let max = (self.resolution - 1) as f32;
+        let to_cell = |value: f32| (value / self.cell_size + 1.0).clamp(0.0, max);
+        let (min_x, max_x) = (
+            to_cell(center.x - radius).ceil(),
+            to_cell(center.x + radius),
+        );
+        let (min_y, max_y) = (
+            to_cell(center.y - radius).ceil(),
+            to_cell(center.y + radius),
+        );
+
+        (min_y as u32..=max_y as u32)
+            .flat_map(move |y| (min_x as u32..=max_x as u32).map(move |x| (x, y)))
+            .filter(move |(x, y)| {
+                let position = Vector2::new(*x as f32 - 1.0, *y as f32 - 1.0) * self.cell_size;
+                (position - center).norm_squared() <= radius * radius
+            })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A field with every kind empty except `layer` of timber, regrowing at `regeneration`, over
+    /// a terrain of 8 quads of one world unit each
+    fn field(layer: Vec<f32>, regeneration: f32) -> ResourceField {
+        let resolution = 10;
+        let mut quantities =
+            vec![vec![0.0; (resolution * resolution) as usize]; ResourceKind::COUNT];
+        quantities[ResourceKind::Timber.index()] = layer;
+        let mut regeneration_rates = [0.0; ResourceKind::COUNT];
+        regeneration_rates[ResourceKind::Timber.index()] = regeneration;
+
+        ResourceField {
+            capacities: quantities.clone(),
+            quantities,
+            full_quantities: [100.0; ResourceKind::COUNT],
+            regeneration: regeneration_rates,
+            resolution,
+            cell_size: 1.0,
+            dirty: None,
+        }
+    }
+
+    fn uniform_field(quantity: f32, regeneration: f32) -> ResourceField {
+        field(vec![quantity; 100], regeneration)
+    }
+
+    fn timber(field: &ResourceField, x: u32, y: u32) -> f32 {
+        field.quantities[ResourceKind::Timber.index()][(y * field.resolution + x) as usize]
+    }
+
+    #[test]
+    fn positions_map_past_the_border() {
+        let field = uniform_field(1.0, 0.0);
+
+        assert_eq!(field.cell(Vector2::new(0.0, 0.0)), Some((1, 1)));
+        assert_eq!(field.cell(Vector2::new(3.0, 2.2)), Some((4, 3)));
+        assert_eq!(field.cell(Vector2::new(8.0, 8.0)), Some((9, 9)));
+        assert_eq!(field.cell(Vector2::new(-2.0, 4.0)), None);
+    }
+
+    #[test]
+    fn extraction_takes_from_the_cells_in_reach() {
+        let mut field = uniform_field(2.0, 0.0);
+        let center = Vector2::new(4.0, 4.0);
+
+        // The centre cell and its four direct neighbours
+        assert_eq!(
+            field.quantity_within(ResourceKind::Timber, center, 1.0),
+            10.0
+        );
+        assert_eq!(field.extract(ResourceKind::Timber, center, 1.0, 4.0), 4.0);
+        assert!((field.quantity_within(ResourceKind::Timber, center, 1.0) - 6.0).abs() < 1e-5);
+        assert_eq!(timber(&field, 5, 5), timber(&field, 6, 5));
+        assert_eq!(timber(&field, 7, 5), 2.0);
+
+        // Asking for more than is left takes the rest
+        let rest = field.extract(ResourceKind::Timber, center, 1.0, 100.0);
+        assert!((rest - 6.0).abs() < 1e-5);
+        assert_eq!(
+            field.quantity_within(ResourceKind::Timber, center, 1.0),
+            0.0
+        );
+        assert_eq!(field.extract(ResourceKind::Timber, center, 1.0, 1.0), 0.0);
+        assert_eq!(field.extract(ResourceKind::Ore, center, 1.0, 1.0), 0.0);
+    }
+
+    #[test]
+    fn extraction_draws_cells_down_in_proportion() {
+        let mut layer = vec![0.0; 100];
+        layer[5 * 10 + 5] = 4.0;
+        layer[5 * 10 + 6] = 2.0;
+        let mut field = field(layer, 0.0);
+
+        // Cells (5, 5) and (6, 5) sit at world positions (4, 4) and (5, 4)
+        let taken = field.extract(ResourceKind::Timber, Vector2::new(4.5, 4.0), 1.0, 3.0);
+
+        assert_eq!(taken, 3.0);
+        assert_eq!(timber(&field, 5, 5), 2.0);
+        assert_eq!(timber(&field, 6, 5), 1.0);
+    }
+
+    #[test]
+    fn regrowth_stops_at_the_capacity() {
+        let mut field = uniform_field(1.0, 0.25);
+        field.extract(ResourceKind::Timber, Vector2::new(4.0, 4.0), 0.5, 1.0);
+        assert_eq!(timber(&field, 5, 5), 0.0);
+
+        field.update(2.0);
+        assert_eq!(timber(&field, 5, 5), 0.5);
+        field.update(10.0);
+        assert_eq!(timber(&field, 5, 5), 1.0);
+        assert!(field.quantities[ResourceKind::Timber.index()]
+            .iter()
+            .all(|quantity| *quantity == 1.0));
+    }
+
+    #[test]
+    fn changes_are_tracked_in_one_rectangle() {
+        let mut field = uniform_field(1.0, 0.0);
+        assert!(!field.is_dirty());
+
+        field.extract(ResourceKind::Timber, Vector2::new(1.0, 6.0), 0.5, 0.5);
+        field.extract(ResourceKind::Timber, Vector2::new(5.0, 2.0), 0.5, 0.5);
+
+        assert!(field.is_dirty());
+        assert_eq!(
+            field.dirty,
+            Some(CellRect {
+                min_x: 2,
+                min_y: 3,
+                max_x: 6,
+                max_y: 7,
+            })
+        );
+
+        // Nothing grows back, so an update leaves the rectangle as it was
+        field.update(1.0);
+        assert_eq!(field.dirty.unwrap().max_x, 6);
+    }
+}
@@ -1,122 +1,374 @@
+use std::fmt::Display;
+
 use gamezap::texture::Texture;
 use nalgebra::Vector2;
-use rand::{Rng, SeedableRng};
+use rand::{seq::SliceRandom, Rng, SeedableRng};
 use rand_chacha::ChaCha8Rng;
 use rayon::iter::{IntoParallelIterator, ParallelIterator};
 
-use crate::perlin_noise::PerlinNoise;
+use crate::{
+    biome::BiomeMap,
+    heightmap::Heightmap,
+    perlin_noise::PerlinNoise,
+    poisson_disk::PoissonDiskSampler,
+    world_config::{ResourceConfig, ResourceKind, ResourcesConfig},
+};
 
 #[derive(Debug)]
-pub struct ResourceMap {
+pub enum ResourceError {
+    /// Fewer than `requested` splats fit in the pixels the resource may lie in while staying
+    /// `spacing` pixels apart
+    NotEnoughRoom {
+        kind: ResourceKind,
+        placed: usize,
+        requested: usize,
+        spacing: f32,
+    },
+}
+
+impl Display for ResourceError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::NotEnoughRoom {
+                kind,
+                placed,
+                requested,
+                spacing,
+            } => write!(
+                f,
+                "only {placed} of {requested} {} splats fit {spacing} pixels apart in the \
+                 allowed biomes, heights and moisture",
+                kind.name()
+            ),
+        }
+    }
+}
+
+impl std::error::Error for ResourceError {}
+
+#[derive(Debug)]
+/// Splats of one kind of resource, confined to the cells that meet its placement constraints
+pub struct ResourceDeposits {
+    kind: ResourceKind,
     origin_points: Vec<Vector2<f32>>,
     magnitude: f32,
     spread: f32,
+    /// Whether each pixel may hold the resource
+    allowed: Vec<bool>,
+    texture_size: u32,
 }
 
-impl ResourceMap {
+impl ResourceDeposits {
+    /// Places the splats of `kind`. Splat origins are spread over the `allowed` pixels with
+    /// Poisson-disk sampling, so no two are closer than `spread_between_splats`, and every origin
+    /// gets `points_per_splat` more points scattered around it
     pub fn new(
-        splat_count: usize,
-        splat_spread: f32,
-        spread_between_splats: f32,
-        points_per_splat: usize,
-        magnitude: f32,
-        spread: f32,
+        kind: ResourceKind,
+        config: &ResourceConfig,
+        allowed: Vec<bool>,
         texture_size: u32,
         seed: u64,
-    ) -> Self {
+    ) -> Result<Self, ResourceError> {
         let mut rng = ChaCha8Rng::seed_from_u64(seed);
+
+        let mut origins = PoissonDiskSampler::new(
+            texture_size as f32,
+            texture_size as f32,
+            config.spread_between_splats,
+            PoissonDiskSampler::DEFAULT_ATTEMPTS,
+        )
+        .sample(&mut rng, |point| {
+            allowed[(point.y as u32 * texture_size + point.x as u32) as usize]
+        });
+        if origins.len() < config.splat_count {
+            return Err(ResourceError::NotEnoughRoom {
+                kind,
+                placed: origins.len(),
+                requested: config.splat_count,
+                spacing: config.spread_between_splats,
+            });
+        }
+        origins.shuffle(&mut rng);
+        origins.truncate(config.splat_count);
+
+        let splat_spread = config.splat_spread;
         let mut origin_points = Vec::new();
-        for i in 0..splat_count {
-            let mut test = Vector2::new(
-                rng.gen_range(0..texture_size) as f32,
-                rng.gen_range(0..texture_size) as f32,
-            );
-            for mut j in 0..(i) {
-                let dist_vector: Vector2<f32> = test - origin_points[j * points_per_splat];
-                let dist_squared = dist_vector.dot(&dist_vector);
-                /* dbg!(
-                    &test,
-                    &dist_squared,
-                    spread_between_splats * spread_between_splats
-                ); */
-                if dist_squared < spread_between_splats * spread_between_splats {
-                    test = Vector2::new(
-                        rng.gen_range(0..texture_size) as f32,
-                        rng.gen_range(0..texture_size) as f32,
-                    );
-
-                    j = 0;
-                }
+        for origin in origins {
+            // Rarer resources lose some of their splats
+            if rng.gen_bool(config.rarity as f64) {
+                continue;
             }
-            origin_points.push(test);
-            for _ in 1..=points_per_splat {
-                let splat_origin = &origin_points[i * points_per_splat];
+            origin_points.push(origin);
+            for _ in 0..config.points_per_splat {
                 origin_points.push(Vector2::new(
-                    rng.gen_range((splat_origin.x - splat_spread)..(splat_origin.x + splat_spread)),
-                    rng.gen_range((splat_origin.y - splat_spread)..(splat_origin.y + splat_spread)),
+                    rng.gen_range((origin.x - splat_spread)..(origin.x + splat_spread)),
+                    rng.gen_range((origin.y - splat_spread)..(origin.y + splat_spread)),
                 ));
             }
         }
 
-        ResourceMap {
+        Ok(Self {
+            kind,
             origin_points,
-            magnitude,
-            spread,
+            magnitude: config.magnitude,
+            spread: config.spread,
+            allowed,
+            texture_size,
+        })
+    }
+
+    pub fn kind(&self) -> ResourceKind {
+        self.kind
+    }
+
+    pub fn origin_points(&self) -> &[Vector2<f32>] {
+        &self.origin_points
+    }
+
+    /// Amount of the resource in a pixel, in `[0, 1]`. The splat points are blended with a smooth
+    /// minimum of their distances, and the amount falls off over `spread` pixels at the edge
+    pub fn amount(&self, x: u32, y: u32) -> f32 {
+        if !self.allowed[(y * self.texture_size + x) as usize] {
+            return 0.0;
+        }
+        let (x, y) = (x as f32, y as f32);
+
+        let Some((_, min_distance)) = self
+            .origin_points
+            .iter()
+            .map(|point| ((point.x - x) * (point.x - x) + (point.y - y) * (point.y - y)).sqrt())
+            .enumerate()
+            .reduce(|acc, (i, e)| {
+                if i == 0 {
+                    return (0, e);
+                }
+                let k = self.magnitude;
+                let h = (0.5 + 0.5 * (e - acc.1) / k).clamp(0.0, 1.0);
+                (i, PerlinNoise::lerp(e, acc.1, h) - k * h * (1.0 - h))
+            })
+        else {
+            return 0.0;
+        };
+
+        if min_distance <= self.magnitude {
+            (self.magnitude - min_distance).clamp(0.0, self.spread) / self.spread
+        } else {
+            0.0
         }
     }
 
-    pub fn create_resource_map(
-        &self,
-        device: &wgpu::Device,
-        queue: &wgpu::Queue,
-        resolution: u32,
-    ) -> Texture {
-        let pixels = (0..resolution * resolution)
+    /// Amount of the resource in every pixel, row by row
+    pub fn amount_layer(&self) -> Vec<f32> {
+        let resolution = self.texture_size;
+        (0..resolution * resolution)
             .into_par_iter()
-            .flat_map(|i| {
-                let y = (i / resolution) as f32;
-                let x = (i % resolution) as f32;
+            .map(|i| self.amount(i % resolution, i / resolution))
+            .collect()
+    }
+}
 
-                let min_distance = self
-                    .origin_points
-                    .iter()
-                    .map(|point| {
-                        ((point.x - x) * (point.x - x) + (point.y - y) * (point.y - y)).sqrt()
+#[derive(Debug)]
+/// Deposits of every kind of resource. Each kind has its own splats and only lies in the
+/// biomes, heights and moisture its config allows
+pub struct ResourceMap {
+    /// One entry per kind, in the order of [`ResourceKind::ALL`]
+    deposits: Vec<ResourceDeposits>,
+    texture_size: u32,
+}
+
+impl ResourceMap {
+    /// Kinds of resource packed into each texture, one per channel
+    pub const KINDS_PER_TEXTURE: usize = 4;
+
+    /// Places every kind of resource over a map whose textures have one pixel per heightmap cell.
+    /// Fails when the splats of a kind do not fit where that kind may lie
+    pub fn new(
+        config: &ResourcesConfig,
+        heightmap: &Heightmap,
+        biomes: &BiomeMap,
+        seed: u64,
+    ) -> Result<Self, ResourceError> {
+        let texture_size = heightmap.resolution();
+        let deposits = ResourceKind::ALL
+            .into_iter()
+            .map(|kind| {
+                let resource = config.get(kind);
+                let allowed = (0..texture_size * texture_size)
+                    .into_par_iter()
+                    .map(|i| {
+                        let (x, y) = (i % texture_size, i / texture_size);
+                        let height = heightmap.get(x, y);
+                        resource.biomes.contains(&biomes.biome(x, y))
+                            && (resource.min_height..=resource.max_height).contains(&height)
+                            && biomes.moisture().get(x, y) >= resource.min_moisture
                     })
-                    .enumerate()
-                    .reduce(|acc, (i, e)| {
-                        if i == 0 {
-                            return (0, e);
+                    .collect();
+
+                ResourceDeposits::new(
+                    kind,
+                    resource,
+                    allowed,
+                    texture_size,
+                    seed.wrapping_add(kind.index() as u64),
+                )
+            })
+            .collect::<Result<_, _>>()?;
+
+        Ok(Self {
+            deposits,
+            texture_size,
+        })
+    }
+
+    pub fn deposits(&self, kind: ResourceKind) -> &ResourceDeposits {
+        &self.deposits[kind.index()]
+    }
+
+    pub fn texture_size(&self) -> u32 {
+        self.texture_size
+    }
+
+    /// Rasterizes the resources without touching the GPU. Every image holds
+    /// [`ResourceMap::KINDS_PER_TEXTURE`] kinds in the order of [`ResourceKind::ALL`], one per
+    /// channel, and channels past the last kind are left empty
+    pub fn create_resource_images(&self) -> Vec<image::RgbaImage> {
+        let layers = self
+            .deposits
+            .iter()
+            .map(ResourceDeposits::amount_layer)
+            .collect::<Vec<_>>();
+
+        layers
+            .chunks(Self::KINDS_PER_TEXTURE)
+            .map(|kinds| {
+                let pixels = (0..(self.texture_size * self.texture_size) as usize)
+                    .flat_map(|i| {
+                        let mut pixel = [0; 4];
+                        for (channel, layer) in pixel.iter_mut().zip(kinds) {
+                            *channel = PerlinNoise::lerp(0.0, 255.0, layer[i]) as u8;
                         }
-                        let k = self.magnitude;
-                        let h = (0.5 + 0.5 * (e - acc.1) / k).clamp(0.0, 1.0);
-                        (i, PerlinNoise::lerp(e, acc.1, h) - k * h * (1.0 - h))
+                        pixel
                     })
-                    .unwrap()
-                    .1;
-                if min_distance <= self.magnitude {
-                    let val = PerlinNoise::lerp(
-                        0.0,
-                        255.0,
-                        (self.magnitude - min_distance).clamp(0.0, self.spread) / self.spread,
-                    ) as u8;
-                    [val, 255, 0, 255]
-                } else {
-                    [0, 0, 0, 255]
-                }
+                    .collect::<Vec<_>>();
+
+                image::RgbaImage::from_vec(self.texture_size, self.texture_size, pixels).unwrap()
             })
-            .collect::<Vec<_>>();
+            .collect()
+    }
+
+    /// Uploads the images from [`ResourceMap::create_resource_images`]
+    pub fn create_resource_maps(&self, device: &wgpu::Device, queue: &wgpu::Queue) -> Vec<Texture> {
+        self.create_resource_images()
+            .iter()
+            .enumerate()
+            .map(|(i, image)| {
+                Texture::from_rgba(
+                    device,
+                    queue,
+                    image,
+                    Some(&format!("Resource Map {i}")),
+                    true,
+                    true,
+                )
+                .unwrap()
+            })
+            .collect()
+    }
+}
 
-        let resource_map = image::RgbaImage::from_vec(resolution, resolution, pixels).unwrap();
+#[cfg(test)]
+mod tests {
+    use super::*;
 
-        gamezap::texture::Texture::from_rgba(
-            device,
-            queue,
-            &resource_map,
-            Some("Resource Map"),
-            true,
-            true,
+    fn config(splat_count: usize, spread_between_splats: f32) -> ResourceConfig {
+        ResourceConfig {
+            splat_count,
+            spread_between_splats,
+            splat_spread: 5.0,
+            points_per_splat: 3,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn splats_keep_their_distance() {
+        let texture_size = 128;
+        for seed in 0..5 {
+            let deposits = ResourceDeposits::new(
+                ResourceKind::Ore,
+                &config(12, 25.0),
+                vec![true; (texture_size * texture_size) as usize],
+                texture_size,
+                seed,
+            )
+            .unwrap();
+
+            let splats = deposits.origin_points().chunks(4).collect::<Vec<_>>();
+            assert_eq!(splats.len(), 12);
+            for (i, splat) in splats.iter().enumerate() {
+                // Every point of a splat is scattered around that splat's own origin
+                assert!(splat[1..]
+                    .iter()
+                    .all(|point| (point - splat[0]).abs().max() <= 5.0));
+                for other in &splats[i + 1..] {
+                    assert!((splat[0] - other[0]).norm() >= 25.0);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn splats_only_start_in_allowed_pixels() {
+        let texture_size = 64;
+        let allowed = (0..texture_size * texture_size)
+            .map(|i| i % texture_size >= 48)
+            .collect::<Vec<_>>();
+        let deposits = ResourceDeposits::new(
+            ResourceKind::Timber,
+            &config(3, 8.0),
+            allowed.clone(),
+            texture_size,
+            2,
         )
-        .unwrap()
+        .unwrap();
+
+        for origin in deposits.origin_points().chunks(4).map(|splat| splat[0]) {
+            assert!(origin.x >= 48.0);
+        }
+        assert_eq!(deposits.amount(10, 10), 0.0);
+    }
+
+    #[test]
+    fn crowded_splats_are_an_error() {
+        let texture_size = 32;
+        let result = ResourceDeposits::new(
+            ResourceKind::Stone,
+            &config(5, 40.0),
+            vec![true; (texture_size * texture_size) as usize],
+            texture_size,
+            0,
+        );
+
+        assert!(matches!(
+            result,
+            Err(ResourceError::NotEnoughRoom { requested: 5, .. })
+        ));
+    }
+
+    #[test]
+    fn nowhere_to_lie_is_an_error() {
+        let texture_size = 32;
+        let result = ResourceDeposits::new(
+            ResourceKind::Oil,
+            &config(1, 10.0),
+            vec![false; (texture_size * texture_size) as usize],
+            texture_size,
+            0,
+        );
+
+        assert!(matches!(
+            result,
+            Err(ResourceError::NotEnoughRoom { placed: 0, .. })
+        ));
     }
 }
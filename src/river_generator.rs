@@ -3,22 +3,96 @@ use rand::{Rng, SeedableRng};
 use rand_chacha::ChaCha8Rng;
 use rayon::prelude::*;
 
-use crate::perlin_noise::PerlinNoise;
+use crate::{
+    heightmap::Heightmap, hydrology::Drainage, perlin_noise::PerlinNoise,
+    world_config::RiverFalloff,
+};
+
+#[derive(Debug, Clone)]
+/// Radius of a river along its curve parameter. It grows linearly from the source to the mouth,
+/// and wobbles by up to `noise_amplitude` of itself following a smooth 1D value noise
+pub struct RiverWidth {
+    /// Radius at the start of the river, in world units
+    pub source: f32,
+    /// Radius at the end of the river, in world units
+    pub mouth: f32,
+    pub noise_amplitude: f32,
+    noise: Vec<f32>,
+    /// Part of the noise this width covers, so that slices of a river keep its wobble
+    noise_range: (f32, f32),
+}
+
+impl RiverWidth {
+    const NOISE_KNOT_COUNT: usize = 8;
+
+    pub fn new(source: f32, mouth: f32, noise_amplitude: f32, seed: u64) -> Self {
+        let mut rng = ChaCha8Rng::seed_from_u64(seed);
+        let noise = (0..Self::NOISE_KNOT_COUNT)
+            .map(|_| rng.gen_range(-1.0..=1.0))
+            .collect();
+
+        Self {
+            source,
+            mouth,
+            noise_amplitude,
+            noise,
+            noise_range: (0.0, 1.0),
+        }
+    }
+
+    /// The same radius along the whole river
+    pub fn uniform(size: f32) -> Self {
+        Self::new(size, size, 0.0, 0)
+    }
+
+    /// Radius of the river at `t`, in world units
+    pub fn radius(&self, t: f32) -> f32 {
+        let t = t.clamp(0.0, 1.0);
+        let noise_t = PerlinNoise::lerp(self.noise_range.0, self.noise_range.1, t)
+            * (self.noise.len() - 1) as f32;
+        let knot = (noise_t.floor() as usize).min(self.noise.len() - 2);
+        let local_t = noise_t - knot as f32;
+        let noise = PerlinNoise::lerp(
+            self.noise[knot],
+            self.noise[knot + 1],
+            local_t * local_t * (3.0 - 2.0 * local_t),
+        );
+
+        PerlinNoise::lerp(self.source, self.mouth, t) * (1.0 + self.noise_amplitude * noise)
+    }
+
+    /// Upper bound of the radius anywhere along the river
+    pub fn max_radius(&self) -> f32 {
+        self.source.max(self.mouth) * (1.0 + self.noise_amplitude)
+    }
+
+    /// Width of the part of the river between the curve parameters `start` and `end`
+    pub fn slice(&self, start: f32, end: f32) -> Self {
+        let noise_t = |t: f32| PerlinNoise::lerp(self.noise_range.0, self.noise_range.1, t);
+        Self {
+            source: PerlinNoise::lerp(self.source, self.mouth, start),
+            mouth: PerlinNoise::lerp(self.source, self.mouth, end),
+            noise_amplitude: self.noise_amplitude,
+            noise: self.noise.clone(),
+            noise_range: (noise_t(start), noise_t(end)),
+        }
+    }
+}
 
 #[derive(Debug)]
-/// A river is represented by a bezier curve. The curviness of the river is produced iteratively.
-/// The size parameter is the radius around the curve at which pixels will be considered to be part
-/// of the river. Might introduce a falloff parameter later
+/// A river is represented by a chain of cubic bezier curves. Segment `i` uses `points[3 * i]` to
+/// `points[3 * i + 3]`, so neighbouring segments share an end point, and the control points on
+/// either side of a shared end point are kept mirrored so the river bends smoothly. The curviness
+/// of the river is produced iteratively. The width gives the radius around the curve at which
+/// pixels will be considered to be part of the river, and can change along its length
 pub struct River {
-    pub starting_point: Vector2<f32>,
-    pub ending_point: Vector2<f32>,
-    pub control_points: [Vector2<f32>; 2],
-    size: f32,
+    pub points: Vec<Vector2<f32>>,
+    width: RiverWidth,
     rng: ChaCha8Rng,
 }
 
 impl River {
-    pub fn new(terrain_size: f32, size: f32, seed: u64) -> Self {
+    pub fn new(terrain_size: f32, width: RiverWidth, segment_count: usize, seed: u64) -> Self {
         let mut rng = ChaCha8Rng::seed_from_u64(seed);
 
         let starting_side = rng.gen_range(0..4);
@@ -47,150 +121,579 @@ impl River {
             _ => Vector2::zeros(),
         };
 
-        let control_points = [
-            PerlinNoise::lerp(starting_point, ending_point, 1.0 / 3.0)
-                + Vector2::new(rng.gen_range(-0.1..=0.1), rng.gen_range(-0.1..=0.1)),
-            PerlinNoise::lerp(starting_point, ending_point, 2.0 / 3.0)
-                + Vector2::new(rng.gen_range(-0.1..=0.1), rng.gen_range(-0.1..=0.1)),
-        ];
+        Self::between_with_rng(starting_point, ending_point, width, segment_count, rng)
+    }
 
-        Self {
+    /// Creates a river flowing from `starting_point` to `ending_point`, with control points
+    /// jittered around the straight line between them
+    pub fn between(
+        starting_point: Vector2<f32>,
+        ending_point: Vector2<f32>,
+        width: RiverWidth,
+        segment_count: usize,
+        seed: u64,
+    ) -> Self {
+        Self::between_with_rng(
             starting_point,
             ending_point,
-            control_points,
-            size,
-            rng,
+            width,
+            segment_count,
+            ChaCha8Rng::seed_from_u64(seed),
+        )
+    }
+
+    fn between_with_rng(
+        starting_point: Vector2<f32>,
+        ending_point: Vector2<f32>,
+        width: RiverWidth,
+        segment_count: usize,
+        mut rng: ChaCha8Rng,
+    ) -> Self {
+        let point_count = 3 * segment_count.max(1);
+        let points = (0..=point_count)
+            .map(|i| {
+                let point =
+                    PerlinNoise::lerp(starting_point, ending_point, i as f32 / point_count as f32);
+                if i % 3 == 0 {
+                    point
+                } else {
+                    point + Vector2::new(rng.gen_range(-0.1..=0.1), rng.gen_range(-0.1..=0.1))
+                }
+            })
+            .collect::<Vec<_>>();
+
+        let mut river = Self { points, width, rng };
+        river.smooth_joins();
+        river
+    }
+
+    /// Creates a river passing through every knot, using a Catmull-Rom spline converted to
+    /// bezier segments
+    pub fn from_knots(knots: &[Vector2<f32>], width: RiverWidth, seed: u64) -> Self {
+        let knot = |i: isize| knots[i.clamp(0, knots.len() as isize - 1) as usize];
+
+        let mut points = vec![knots[0]];
+        for i in 0..knots.len().saturating_sub(1) as isize {
+            points.push(knot(i) + (knot(i + 1) - knot(i - 1)) / 6.0);
+            points.push(knot(i + 1) - (knot(i + 2) - knot(i)) / 6.0);
+            points.push(knot(i + 1));
+        }
+        if points.len() == 1 {
+            points.extend([knots[0]; 3]);
         }
+
+        Self {
+            points,
+            width,
+            rng: ChaCha8Rng::seed_from_u64(seed),
+        }
+    }
+
+    /// Creates a river that follows the terrain. The source is picked at random among the highest
+    /// cells, the path is traced along `drainage` until it reaches the coast, or the map edge if
+    /// there is no ocean, and a spline is passed through every few cells of that path.
+    pub fn downhill(
+        heightmap: &Heightmap,
+        drainage: &Drainage,
+        width: RiverWidth,
+        seed: u64,
+    ) -> Self {
+        let mut rng = ChaCha8Rng::seed_from_u64(seed);
+
+        let source_candidates = Self::source_candidates(heightmap, Self::SOURCE_HEIGHT_QUANTILE);
+        let source = if source_candidates.is_empty() {
+            let resolution = heightmap.resolution();
+            (resolution * resolution / 2 + resolution / 2) as usize
+        } else {
+            source_candidates[rng.gen_range(0..source_candidates.len())]
+        };
+
+        let path = drainage.flow_path(source);
+
+        Self::from_path(heightmap, &path, width, rng.gen())
+    }
+
+    /// Indices of the cells away from the map edge whose height is in the top `1 - quantile`
+    /// fraction of the terrain
+    pub fn source_candidates(heightmap: &Heightmap, quantile: f32) -> Vec<usize> {
+        let resolution = heightmap.resolution();
+
+        let mut sorted_heights = heightmap.heights().to_vec();
+        sorted_heights.sort_by(f32::total_cmp);
+        let source_height = sorted_heights[((sorted_heights.len() - 1) as f32 * quantile) as usize];
+
+        (0..heightmap.heights().len())
+            .filter(|i| {
+                let x = *i as u32 % resolution;
+                let y = *i as u32 / resolution;
+                x > 0
+                    && y > 0
+                    && x < resolution - 1
+                    && y < resolution - 1
+                    && heightmap.heights()[*i] >= source_height
+            })
+            .collect()
+    }
+
+    /// Creates a river through a path of heightmap cells, placing a knot every few cells
+    pub fn from_path(heightmap: &Heightmap, path: &[usize], width: RiverWidth, seed: u64) -> Self {
+        let resolution = heightmap.resolution();
+
+        let mut knots = path
+            .iter()
+            .step_by(Self::DOWNHILL_KNOT_SPACING)
+            .chain(path.last())
+            .map(|i| heightmap.cell_position(*i as u32 % resolution, *i as u32 / resolution))
+            .collect::<Vec<_>>();
+        knots.dedup();
+
+        Self::from_knots(&knots, width, seed)
+    }
+
+    /// Cuts out the part of the river between the curve parameters `start` and `end` as a new
+    /// river, keeping the width it had there
+    pub fn slice(&self, start: f32, end: f32) -> Self {
+        let segment_count = self.segment_count();
+        let to_segment = |t: f32| {
+            let scaled_t = t.clamp(0.0, 1.0) * segment_count as f32;
+            let segment = (scaled_t.floor() as usize).min(segment_count - 1);
+            (segment, scaled_t - segment as f32)
+        };
+
+        let (start_segment, start_t) = to_segment(start);
+        let (mut end_segment, mut end_t) = to_segment(end);
+        // An end landing exactly on a segment boundary belongs to the previous segment
+        if end_t == 0.0 && end_segment > start_segment {
+            end_segment -= 1;
+            end_t = 1.0;
+        }
+
+        let mut points = Vec::with_capacity(3 * (end_segment - start_segment + 1) + 1);
+        for segment in start_segment..=end_segment {
+            let mut bezier_points: [Vector2<f32>; 4] = self.points[3 * segment..3 * segment + 4]
+                .try_into()
+                .unwrap();
+
+            let local_start = if segment == start_segment {
+                start_t
+            } else {
+                0.0
+            };
+            let local_end = if segment == end_segment { end_t } else { 1.0 };
+
+            if local_end < 1.0 {
+                bezier_points = Self::split_bezier(&bezier_points, local_end).0;
+            }
+            if local_start > 0.0 && local_end > 0.0 {
+                bezier_points = Self::split_bezier(&bezier_points, local_start / local_end).1;
+            }
+
+            if points.is_empty() {
+                points.push(bezier_points[0]);
+            }
+            points.extend_from_slice(&bezier_points[1..]);
+        }
+
+        Self {
+            points,
+            width: self.width.slice(start, end),
+            rng: self.rng.clone(),
+        }
+    }
+
+    /// Splits a cubic bezier at `t` into two curves covering the same path, using de Casteljau's
+    /// algorithm
+    fn split_bezier(
+        bezier_points: &[Vector2<f32>; 4],
+        t: f32,
+    ) -> ([Vector2<f32>; 4], [Vector2<f32>; 4]) {
+        let [p_0, p_1, p_2, p_3] = *bezier_points;
+        let p_01 = PerlinNoise::lerp(p_0, p_1, t);
+        let p_12 = PerlinNoise::lerp(p_1, p_2, t);
+        let p_23 = PerlinNoise::lerp(p_2, p_3, t);
+        let p_012 = PerlinNoise::lerp(p_01, p_12, t);
+        let p_123 = PerlinNoise::lerp(p_12, p_23, t);
+        let p_0123 = PerlinNoise::lerp(p_012, p_123, t);
+
+        ([p_0, p_01, p_012, p_0123], [p_0123, p_123, p_23, p_3])
+    }
+
+    pub fn width(&self) -> &RiverWidth {
+        &self.width
+    }
+
+    pub fn width_mut(&mut self) -> &mut RiverWidth {
+        &mut self.width
+    }
+
+    pub fn segment_count(&self) -> usize {
+        (self.points.len() - 1) / 3
+    }
+
+    pub fn starting_point(&self) -> Vector2<f32> {
+        self.points[0]
+    }
+
+    pub fn ending_point(&self) -> Vector2<f32> {
+        *self.points.last().unwrap()
     }
 
     /// Iteratively shifts around the bezier control points, favoring to go outwards instead of
-    /// inwards.
+    /// inwards. Each step is scaled down by the number of segments, so longer chains meander
+    /// instead of looping back on themselves.
     pub fn random_shift(&mut self, iterations: usize) {
+        let starting_point = self.starting_point();
+        let ending_point = self.ending_point();
+        let point_count = self.points.len() - 1;
+        let step_size = 1.0 / self.segment_count() as f32;
+
         for _ in 0..iterations {
-            self.control_points = self
-                .control_points
-                .iter()
-                .enumerate()
-                .map(|(i, control_point)| {
-                    let persuasion_vector = (*control_point
-                        - PerlinNoise::lerp(
-                            self.starting_point,
-                            self.ending_point,
-                            (i as f32 + 1.0) / 3.0,
-                        ))
-                    .normalize();
-                    let random_vector =
-                        Vector2::new(self.rng.gen_range(-1.0..1.0), self.rng.gen_range(-1.0..1.0))
-                            .normalize();
-
-                    let weighted_vector = random_vector * persuasion_vector.dot(&random_vector);
-
-                    control_point + weighted_vector
-                })
-                .collect::<Vec<_>>()
-                .try_into()
-                .unwrap();
+            for i in (0..=point_count).filter(|i| i % 3 != 0) {
+                let control_point = self.points[i];
+                let persuasion_vector = (control_point
+                    - PerlinNoise::lerp(
+                        starting_point,
+                        ending_point,
+                        i as f32 / point_count as f32,
+                    ))
+                .normalize();
+                let random_vector =
+                    Vector2::new(self.rng.gen_range(-1.0..1.0), self.rng.gen_range(-1.0..1.0))
+                        .normalize();
+
+                let weighted_vector =
+                    random_vector * persuasion_vector.dot(&random_vector) * step_size;
+
+                self.points[i] = control_point + weighted_vector;
+            }
+            self.smooth_joins();
+        }
+    }
+
+    /// Moves every shared end point to the middle of its two neighbouring control points, so the
+    /// tangent carries over between segments
+    fn smooth_joins(&mut self) {
+        for i in (3..self.points.len() - 1).step_by(3) {
+            self.points[i] = (self.points[i - 1] + self.points[i + 1]) / 2.0;
         }
     }
 
+    /// Downhill rivers start somewhere in this top fraction of the terrain
+    const SOURCE_HEIGHT_QUANTILE: f32 = 0.9;
+    /// Number of path cells between the knots of a downhill river
+    const DOWNHILL_KNOT_SPACING: usize = 20;
     const NEWTON_METHOD_ITERATIONS: usize = 5;
     const EVALUATION_POINT_COUNT: usize = 5;
-    pub fn create_texture(
+    const SEGMENT_SAMPLE_COUNT: usize = 20;
+    const CARVE_SAMPLES_PER_SEGMENT: usize = 64;
+    /// Gaussian falloff is `exp(-k d²)`, rescaled to reach zero at the edge of the river
+    const GAUSSIAN_FALLOFF_SHARPNESS: f32 = 4.5;
+
+    fn segment_coefficients(&self) -> Vec<[Vector2<f32>; 4]> {
+        self.points
+            .windows(4)
+            .step_by(3)
+            .map(|bezier_points| {
+                [
+                    bezier_points[0],
+                    -3.0 * bezier_points[0] + 3.0 * bezier_points[1],
+                    3.0 * bezier_points[0] - 6.0 * bezier_points[1] + 3.0 * bezier_points[2],
+                    -1.0 * bezier_points[0] + 3.0 * bezier_points[1] - 3.0 * bezier_points[2]
+                        + bezier_points[3],
+                ]
+            })
+            .collect()
+    }
+
+    /// Point on the river at `t`, where `t` runs from 0 at the start to 1 at the end, with every
+    /// segment covering an equal share
+    pub fn evaluate(&self, t: f32) -> Vector2<f32> {
+        Self::evaluate_segments(&self.segment_coefficients(), t)
+    }
+
+    fn evaluate_segments(segments: &[[Vector2<f32>; 4]], t: f32) -> Vector2<f32> {
+        let scaled_t = t.clamp(0.0, 1.0) * segments.len() as f32;
+        let segment = (scaled_t.floor() as usize).min(segments.len() - 1);
+        Self::bezier_evaluate(&segments[segment], scaled_t - segment as f32)
+    }
+
+    /// Finds the curve parameter `t` of the point on the river closest to `position`, along with
+    /// the distance to it
+    pub fn closest_point(&self, position: Vector2<f32>) -> (f32, f32) {
+        let segments = self.segment_coefficients();
+        Self::closest_point_on_segments(&segments, 0..segments.len(), position)
+    }
+
+    /// Runs the Newton projection on each of the candidate segments, returning the closest
+    /// result as a curve parameter over the whole river
+    fn closest_point_on_segments(
+        segments: &[[Vector2<f32>; 4]],
+        candidates: impl Iterator<Item = usize>,
+        position: Vector2<f32>,
+    ) -> (f32, f32) {
+        candidates
+            .map(|segment| {
+                let (t, distance) = Self::closest_point_on_curve(&segments[segment], position);
+                ((segment as f32 + t) / segments.len() as f32, distance)
+            })
+            .fold((0.0, f32::MAX), |closest, current| {
+                if current.1 < closest.1 {
+                    current
+                } else {
+                    closest
+                }
+            })
+    }
+
+    fn closest_point_on_curve(
+        bezier_coefficients: &[Vector2<f32>],
+        position: Vector2<f32>,
+    ) -> (f32, f32) {
+        let mut t_values = Vec::with_capacity(Self::EVALUATION_POINT_COUNT + 3);
+        t_values.push(0.0);
+
+        for i in 0..=Self::EVALUATION_POINT_COUNT {
+            t_values.push(
+                Self::newton_method_evaluate(
+                    bezier_coefficients,
+                    position,
+                    i as f32 / Self::EVALUATION_POINT_COUNT as f32,
+                    Self::NEWTON_METHOD_ITERATIONS,
+                )
+                .clamp(0.0, 1.0),
+            );
+        }
+
+        t_values.push(1.0);
+
+        t_values
+            .into_iter()
+            .map(|t| {
+                (
+                    t,
+                    Self::bezier_evaluate(bezier_coefficients, t).metric_distance(&position),
+                )
+            })
+            .fold((0.0, f32::MAX), |closest, current| {
+                if current.1 < closest.1 {
+                    current
+                } else {
+                    closest
+                }
+            })
+    }
+
+    /// How strongly a pixel at `distance` from the curve, as a fraction of the radius, belongs to
+    /// the river. Runs from 1 at the center to 0 at the edge
+    fn falloff_strength(falloff: RiverFalloff, distance: f32) -> f32 {
+        match falloff {
+            RiverFalloff::Linear => 1.0 - distance,
+            RiverFalloff::Quadratic => 1.0 - distance * distance,
+            RiverFalloff::Smoothstep => 1.0 - distance * distance * (3.0 - 2.0 * distance),
+            RiverFalloff::Gaussian => {
+                let edge = (-Self::GAUSSIAN_FALLOFF_SHARPNESS).exp();
+                ((-Self::GAUSSIAN_FALLOFF_SHARPNESS * distance * distance).exp() - edge)
+                    / (1.0 - edge)
+            }
+        }
+    }
+
+    /// Rasterizes the river into the red channel of an RGBA image, without touching the GPU
+    pub fn create_image(
         &self,
-        device: &wgpu::Device,
-        queue: &wgpu::Queue,
         terrain_size: f32,
         resolution: u32,
-    ) -> gamezap::texture::Texture {
-        let bezier_points = [
-            self.starting_point,
-            self.control_points[0],
-            self.control_points[1],
-            self.ending_point,
-        ];
-        let bezier_coefficients = [
-            bezier_points[0],
-            -3.0 * bezier_points[0] + 3.0 * bezier_points[1],
-            3.0 * bezier_points[0] - 6.0 * bezier_points[1] + 3.0 * bezier_points[2],
-            -1.0 * bezier_points[0] + 3.0 * bezier_points[1] - 3.0 * bezier_points[2]
-                + bezier_points[3],
-        ];
-        let bezier_samples: [Vector2<f32>; 20] = (0..=19).map(|t| {
-            Self::bezier_evaluate(&bezier_coefficients, t as f32 / 19.0)
-        }).collect::<Vec<_>>().try_into().unwrap();
-
-        let river_size = self.size;
+        falloff: RiverFalloff,
+    ) -> image::RgbaImage {
+        let segments = self.segment_coefficients();
+        let segment_samples = segments
+            .iter()
+            .map(|bezier_coefficients| {
+                (0..Self::SEGMENT_SAMPLE_COUNT)
+                    .map(|t| {
+                        Self::bezier_evaluate(
+                            bezier_coefficients,
+                            t as f32 / (Self::SEGMENT_SAMPLE_COUNT - 1) as f32,
+                        )
+                    })
+                    .collect::<Vec<_>>()
+            })
+            .collect::<Vec<_>>();
+
+        let max_radius = self.width.max_radius();
         let pixels = (0..resolution * resolution)
             .into_par_iter()
             .flat_map(|i| {
                 let y = i / resolution;
                 let x = i % resolution;
-                let position = Vector2::new(
-                    x as f32 * terrain_size / resolution as f32,
-                    y as f32 * terrain_size / resolution as f32,
-                );
+                // Pixels line up with the cells of a heightmap of the same resolution
+                let position = Vector2::new(x as f32 - 1.0, y as f32 - 1.0) * terrain_size
+                    / (resolution - 2) as f32;
 
-                let mut min_sample_distance_squared = 100000.0_f32;
-                for sample in &bezier_samples {
-                    let distance_vector = sample - position;
-                    min_sample_distance_squared = min_sample_distance_squared.min(distance_vector.dot(&distance_vector));
-                }
+                // Only segments passing near the pixel are worth projecting onto
+                let candidates = segment_samples
+                    .iter()
+                    .enumerate()
+                    .filter(|(_, samples)| {
+                        samples.iter().any(|sample| {
+                            let distance_vector = sample - position;
+                            distance_vector.dot(&distance_vector) <= max_radius * max_radius * 1.5
+                        })
+                    })
+                    .map(|(segment, _)| segment)
+                    .collect::<Vec<_>>();
 
-                if min_sample_distance_squared > river_size * river_size * 1.5 {
+                if candidates.is_empty() {
                     return [0, 0, 0, 255];
                 }
 
-                let mut t_values = Vec::with_capacity(Self::EVALUATION_POINT_COUNT + 3);
-                t_values.push(0.0);
+                let (t, min_val) =
+                    Self::closest_point_on_segments(&segments, candidates.into_iter(), position);
 
-                for i in 0..=Self::EVALUATION_POINT_COUNT {
-                    t_values.push(
-                        Self::newton_method_evaluate(
-                            &bezier_coefficients,
-                            position,
-                            i as f32 / Self::EVALUATION_POINT_COUNT as f32,
-                            Self::NEWTON_METHOD_ITERATIONS,
-                        )
-                        .clamp(0.0, 1.0),
-                    );
+                let radius = self.width.radius(t);
+                if min_val < radius {
+                    let strength = Self::falloff_strength(falloff, min_val / radius);
+                    return [PerlinNoise::lerp(10.0, 255.0, strength) as u8, 0, 0, 255];
                 }
+                [0, 0, 0, 255]
+            })
+            .collect::<Vec<_>>();
 
-                t_values.push(1.0);
+        image::RgbaImage::from_vec(resolution, resolution, pixels).unwrap()
+    }
 
-                let mut min_val = 10000000.0_f32;
+    /// Lowers the heightmap along the river. The water level follows `water_surface` from the
+    /// starting point, but never rises, so the river always flows downhill towards its end. The
+    /// bed is a parabola `depth` below the water level at the center of the river, spanning the
+    /// local river radius, and the `bank_width` wide band around it is smoothly blended down to
+    /// the water level. A `depth` of zero leaves the terrain untouched.
+    pub fn carve(
+        &self,
+        heightmap: &mut Heightmap,
+        water_surface: &Heightmap,
+        depth: f32,
+        bank_width: f32,
+    ) {
+        self.carve_below(heightmap, water_surface, depth, bank_width, f32::INFINITY);
+    }
 
-                for t in &t_values {
-                    let current_distance =
-                        Self::bezier_evaluate(&bezier_coefficients, *t).metric_distance(&position);
-                    min_val = min_val.min(current_distance);
-                }
-                if min_val < river_size {
-                    return [
-                        PerlinNoise::lerp(
-                            255.0,
-                            10.0,
-                            (min_val* min_val) / (river_size * river_size),
-                        ) as u8,
-                        0,
-                        0,
-                        255,
-                    ];
-                }
-                [0, 0, 0, 255]
+    /// Like [`River::carve`], but the water level also starts no higher than `max_level`. Returns
+    /// the water level at the end of the river, so a river cut into several pieces can be carved
+    /// piece by piece without its bed stepping back up where one piece meets the next.
+    pub fn carve_below(
+        &self,
+        heightmap: &mut Heightmap,
+        water_surface: &Heightmap,
+        depth: f32,
+        bank_width: f32,
+        max_level: f32,
+    ) -> f32 {
+        if depth == 0.0 {
+            return max_level;
+        }
+
+        let segments = self.segment_coefficients();
+
+        let sample_count = Self::CARVE_SAMPLES_PER_SEGMENT * segments.len() + 1;
+        let bezier_samples = (0..sample_count)
+            .map(|i| Self::evaluate_segments(&segments, i as f32 / (sample_count - 1) as f32))
+            .collect::<Vec<_>>();
+
+        let mut water_levels = bezier_samples
+            .iter()
+            .map(|sample| {
+                let cell = water_surface.position_to_cell(*sample);
+                water_surface.sample(cell.x, cell.y)
             })
             .collect::<Vec<_>>();
+        water_levels[0] = water_levels[0].min(max_level);
+        for i in 1..water_levels.len() {
+            water_levels[i] = water_levels[i].min(water_levels[i - 1]);
+        }
 
-        let height_map = image::RgbaImage::from_vec(resolution, resolution, pixels).unwrap();
+        // Any cell within reach of the river is at most half a sample gap further from the
+        // nearest sample than from the curve itself
+        let sample_spacing = bezier_samples
+            .windows(2)
+            .map(|pair| pair[0].metric_distance(&pair[1]))
+            .fold(0.0, f32::max);
+        let cull_distance = self.width.max_radius() + bank_width + sample_spacing / 2.0;
 
-        gamezap::texture::Texture::from_rgba(
-            device,
-            queue,
-            &height_map,
-            Some("River height map"),
-            true,
-            true,
-        )
-        .unwrap()
+        // Bounding box of the samples along every segment, grown by the cull distance, so each
+        // cell only measures its distance to the samples of the few segments passing near it
+        let samples_per_segment = Self::CARVE_SAMPLES_PER_SEGMENT;
+        let segment_bounds = bezier_samples
+            .windows(samples_per_segment + 1)
+            .step_by(samples_per_segment)
+            .map(|samples| {
+                let reach = Vector2::repeat(cull_distance);
+                let min = samples
+                    .iter()
+                    .fold(Vector2::repeat(f32::MAX), |min, sample| min.inf(sample));
+                let max = samples
+                    .iter()
+                    .fold(Vector2::repeat(f32::MIN), |max, sample| max.sup(sample));
+                (min - reach, max + reach)
+            })
+            .collect::<Vec<_>>();
+
+        let resolution = heightmap.resolution();
+        let cell_positions = (0..resolution * resolution)
+            .map(|i| heightmap.cell_position(i % resolution, i / resolution))
+            .collect::<Vec<_>>();
+        heightmap
+            .heights_mut()
+            .par_iter_mut()
+            .enumerate()
+            .for_each(|(i, height)| {
+                let position = cell_positions[i];
+                let is_near = |segment: &usize| {
+                    let (min, max) = segment_bounds[*segment];
+                    position.x >= min.x
+                        && position.y >= min.y
+                        && position.x <= max.x
+                        && position.y <= max.y
+                };
+
+                if (0..segments.len()).filter(is_near).all(|segment| {
+                    let start = segment * samples_per_segment;
+                    bezier_samples[start..=start + samples_per_segment]
+                        .iter()
+                        .all(|sample| sample.metric_distance(&position) > cull_distance)
+                }) {
+                    return;
+                }
+
+                // The closest point of the curve lies within reach of one of the near segments
+                let (t, distance) = Self::closest_point_on_segments(
+                    &segments,
+                    (0..segments.len()).filter(is_near),
+                    position,
+                );
+                let river_size = self.width.radius(t);
+                if distance >= river_size + bank_width {
+                    return;
+                }
+
+                let level_index = t * (sample_count - 1) as f32;
+                let water_level = PerlinNoise::lerp(
+                    water_levels[level_index.floor() as usize],
+                    water_levels[level_index.ceil() as usize],
+                    level_index.fract(),
+                );
+
+                let target = if distance < river_size {
+                    water_level - depth * (1.0 - (distance * distance) / (river_size * river_size))
+                } else {
+                    let bank_t = 1.0 - (distance - river_size) / bank_width;
+                    let blend = bank_t * bank_t * (3.0 - 2.0 * bank_t);
+                    PerlinNoise::lerp(*height, water_level, blend)
+                };
+
+                *height = height.min(target);
+            });
+
+        water_levels[sample_count - 1]
     }
 
     fn bezier_evaluate(bezier_coefficients: &[Vector2<f32>], t: f32) -> Vector2<f32> {
@@ -244,3 +747,210 @@ impl River {
         )
     }
 }
+
+/// Rasterizes several rivers into one image, keeping the strongest value at every pixel
+pub fn create_rivers_image<'a>(
+    rivers: impl IntoIterator<Item = &'a River>,
+    terrain_size: f32,
+    resolution: u32,
+    falloff: RiverFalloff,
+) -> image::RgbaImage {
+    let mut rivers_image =
+        image::RgbaImage::from_pixel(resolution, resolution, image::Rgba([0, 0, 0, 255]));
+    for river in rivers {
+        let river_image = river.create_image(terrain_size, resolution, falloff);
+        for (pixel, river_pixel) in rivers_image.pixels_mut().zip(river_image.pixels()) {
+            pixel[0] = pixel[0].max(river_pixel[0]);
+        }
+    }
+    rivers_image
+}
+
+/// Uploads a river mask made by [`create_rivers_image`]
+pub fn rivers_texture_from_image(
+    device: &wgpu::Device,
+    queue: &wgpu::Queue,
+    rivers_image: &image::RgbaImage,
+) -> gamezap::texture::Texture {
+    gamezap::texture::Texture::from_rgba(
+        device,
+        queue,
+        rivers_image,
+        Some("River height map"),
+        true,
+        true,
+    )
+    .unwrap()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn assert_close(a: Vector2<f32>, b: Vector2<f32>) {
+        assert!(a.metric_distance(&b) < 1e-3, "{a} is not {b}");
+    }
+
+    fn meandering_river() -> River {
+        let mut river = River::new(32.0, RiverWidth::uniform(1.0), 4, 11);
+        river.random_shift(20);
+        river
+    }
+
+    /// A straight river along the x axis, moving at constant speed through its middle segment
+    fn straight_river() -> River {
+        let knots = [0.0, 3.0, 6.0, 9.0].map(|x| Vector2::new(x, 0.0));
+        River::from_knots(&knots, RiverWidth::uniform(1.0), 0)
+    }
+
+    #[test]
+    fn joins_between_segments_are_smooth() {
+        let knots = [(0.0, 0.0), (4.0, 1.0), (5.0, 6.0), (9.0, 4.0), (12.0, 9.0)]
+            .map(|(x, y)| Vector2::new(x, y));
+        for river in [
+            meandering_river(),
+            River::from_knots(&knots, RiverWidth::uniform(1.0), 0),
+        ] {
+            let segments = river.segment_coefficients();
+            for pair in segments.windows(2) {
+                assert_close(
+                    River::bezier_evaluate(&pair[0], 1.0),
+                    River::bezier_evaluate(&pair[1], 0.0),
+                );
+                assert_close(
+                    River::bezier_derivative_evaluate(&pair[0], 1.0),
+                    River::bezier_derivative_evaluate(&pair[1], 0.0),
+                );
+            }
+        }
+
+        // Slicing shortens the end segments, which scales their derivatives but keeps the
+        // direction of the tangent
+        let slice = meandering_river().slice(0.1, 0.9);
+        for pair in slice.segment_coefficients().windows(2) {
+            let incoming = River::bezier_derivative_evaluate(&pair[0], 1.0).normalize();
+            let outgoing = River::bezier_derivative_evaluate(&pair[1], 0.0).normalize();
+            assert_close(incoming, outgoing);
+        }
+    }
+
+    #[test]
+    fn slices_follow_the_original_river() {
+        let river = meandering_river();
+        let slice = river.slice(0.3, 0.8);
+        assert_eq!(slice.segment_count(), 3);
+        assert_close(slice.starting_point(), river.evaluate(0.3));
+        assert_close(slice.ending_point(), river.evaluate(0.8));
+        for i in 0..=20 {
+            let (t, distance) = river.closest_point(slice.evaluate(i as f32 / 20.0));
+            assert!(distance < 1e-3);
+            assert!((0.3 - 1e-3..=0.8 + 1e-3).contains(&t));
+        }
+
+        // Within a single segment the curve parameter maps linearly
+        let slice = river.slice(0.3, 0.45);
+        assert_eq!(slice.segment_count(), 1);
+        for i in 0..=10 {
+            let t = i as f32 / 10.0;
+            assert_close(slice.evaluate(t), river.evaluate(0.3 + 0.15 * t));
+        }
+
+        // Ends on segment boundaries keep whole segments
+        assert_eq!(river.slice(0.0, 1.0).points, river.points);
+        assert_eq!(river.slice(0.25, 0.75).points, river.points[3..=9]);
+    }
+
+    #[test]
+    fn closest_point_projects_onto_the_curve() {
+        let river = straight_river();
+        let segments = river.segment_coefficients();
+
+        let (t, distance) =
+            River::closest_point_on_segments(&segments, 0..3, Vector2::new(4.5, 2.0));
+        assert!((t - 0.5).abs() < 1e-4);
+        assert!((distance - 2.0).abs() < 1e-4);
+
+        // Only the candidate segments are searched
+        let (t, distance) =
+            River::closest_point_on_segments(&segments, 0..1, Vector2::new(4.5, 2.0));
+        assert!((t - 1.0 / 3.0).abs() < 1e-4);
+        assert!((distance - 2.5).abs() < 1e-4);
+
+        // Points past the ends project onto the end points
+        let (t, distance) = river.closest_point(Vector2::new(-2.0, 0.0));
+        assert_eq!(t, 0.0);
+        assert!((distance - 2.0).abs() < 1e-4);
+    }
+
+    #[test]
+    fn width_grows_from_source_to_mouth() {
+        let width = RiverWidth::new(0.4, 1.0, 0.0, 3);
+        assert_eq!(width.radius(0.0), 0.4);
+        assert_eq!(width.radius(1.0), 1.0);
+        assert!((width.radius(0.5) - 0.7).abs() < 1e-6);
+        // The parameter is clamped to the river
+        assert_eq!(width.radius(-1.0), 0.4);
+        assert_eq!(width.radius(2.0), 1.0);
+
+        let slice = width.slice(0.5, 1.0);
+        assert!((slice.source - 0.7).abs() < 1e-6);
+        assert!((slice.radius(0.5) - width.radius(0.75)).abs() < 1e-6);
+    }
+
+    #[test]
+    fn width_noise_stays_within_its_amplitude() {
+        let width = RiverWidth::new(0.4, 1.0, 0.25, 3);
+        let slice = width.slice(0.2, 0.7);
+        let mut wobbles = false;
+        for i in 0..=100 {
+            let t = i as f32 / 100.0;
+            let base = PerlinNoise::lerp(0.4, 1.0, t);
+            let radius = width.radius(t);
+            assert!((radius / base - 1.0).abs() <= 0.25 + 1e-6);
+            assert!(radius <= width.max_radius());
+            wobbles |= (radius - base).abs() > 1e-3;
+
+            // Slices keep the wobble of the part they cover
+            let slice_t = 0.2 + 0.5 * t;
+            assert!((slice.radius(t) - width.radius(slice_t)).abs() < 1e-5);
+        }
+        assert!(wobbles);
+    }
+
+    #[test]
+    fn falloff_runs_from_one_at_the_center_to_zero_at_the_edge() {
+        for falloff in [
+            RiverFalloff::Linear,
+            RiverFalloff::Quadratic,
+            RiverFalloff::Smoothstep,
+            RiverFalloff::Gaussian,
+        ] {
+            assert!((River::falloff_strength(falloff, 0.0) - 1.0).abs() < 1e-6);
+            assert!(River::falloff_strength(falloff, 1.0).abs() < 1e-6);
+            let middle = River::falloff_strength(falloff, 0.5);
+            assert!(middle > 0.0 && middle < 1.0);
+        }
+    }
+
+    #[test]
+    fn carving_only_lowers_cells_within_reach() {
+        // Horizontal river across the middle of a flat map, one world unit per cell
+        let knots = [-1.0, 10.0, 21.0].map(|x| Vector2::new(x, 9.0));
+        let river = River::from_knots(&knots, RiverWidth::uniform(1.5), 0);
+        let flat = Heightmap::from_fn(22, 20.0, |_, _| 0.5);
+        let mut heightmap = flat.clone();
+        river.carve(&mut heightmap, &flat, 0.1, 2.0);
+
+        for y in 0..22 {
+            for x in 0..22 {
+                let distance = (heightmap.cell_position(x, y).y - 9.0).abs();
+                let height = heightmap.get(x, y);
+                if distance < 1.5 {
+                    assert!(height < 0.5 - 0.01);
+                } else {
+                    assert_eq!(height, 0.5);
+                }
+            }
+        }
+    }
+}
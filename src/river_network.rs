@@ -0,0 +1,491 @@
+use std::collections::{HashMap, HashSet};
+
+use nalgebra::Vector2;
+use rand::{Rng, SeedableRng};
+use rand_chacha::ChaCha8Rng;
+
+use crate::{
+    heightmap::Heightmap,
+    hydrology,
+    ocean::OceanMap,
+    perlin_noise::PerlinNoise,
+    river_generator::{River, RiverWidth},
+    world_config::{RiverConfig, RiverMode},
+};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RiverNodeKind {
+    Source,
+    Confluence,
+    Mouth,
+}
+
+#[derive(Debug, Clone)]
+pub struct RiverNode {
+    pub position: Vector2<f32>,
+    pub kind: RiverNodeKind,
+}
+
+#[derive(Debug)]
+/// A stretch of river flowing from node `from` to node `to`, with no confluence in between
+pub struct RiverEdge {
+    pub from: usize,
+    pub to: usize,
+    /// Radius of the river at the downstream end of this edge, in world units, ignoring the
+    /// width noise
+    pub width: f32,
+    pub river: River,
+}
+
+#[derive(Debug)]
+/// A main river with tributaries joining it. Nodes are the sources, the confluences and the
+/// mouth, and edges are the stretches of river between them. At every confluence the main river
+/// takes on the flow of the tributary, so it grows wider downstream.
+pub struct RiverNetwork {
+    pub nodes: Vec<RiverNode>,
+    pub edges: Vec<RiverEdge>,
+}
+
+impl RiverNetwork {
+    /// Tributaries join the main river somewhere within this range of its curve parameter
+    const CONFLUENCE_RANGE: std::ops::Range<f32> = 0.2..0.9;
+    /// Downhill tributaries start somewhere in this top fraction of the terrain
+    const TRIBUTARY_SOURCE_QUANTILE: f32 = 0.5;
+    /// Shortest downhill tributary, and the fewest cells between two confluences
+    const MIN_TRIBUTARY_CELLS: usize = 30;
+    const TRIBUTARY_ATTEMPTS_PER_TRIBUTARY: usize = 50;
+
+    pub fn new(config: &RiverConfig, heightmap: &Heightmap, ocean: &OceanMap, seed: u64) -> Self {
+        let mut rng = ChaCha8Rng::seed_from_u64(seed);
+
+        let (main_river, mut tributaries) = match config.mode {
+            RiverMode::Random => Self::random_rivers(config, heightmap, ocean, &mut rng),
+            RiverMode::Downhill => Self::downhill_rivers(config, heightmap, ocean, &mut rng),
+        };
+        tributaries.sort_by(|a, b| a.0.total_cmp(&b.0));
+
+        let mut nodes = vec![RiverNode {
+            position: main_river.starting_point(),
+            kind: RiverNodeKind::Source,
+        }];
+        let mut edges = Vec::with_capacity(2 * tributaries.len() + 1);
+
+        let mut upstream_node = 0;
+        let mut upstream_t = 0.0;
+        let mut tributary_area = 0.0;
+        for (confluence_t, mut tributary) in tributaries {
+            // Downhill tributaries end on a cell of the main river's path, which the spline only
+            // passes near, so their end is moved onto the curve where the main stretches meet
+            let confluence = main_river.evaluate(confluence_t);
+            let offset = confluence - tributary.ending_point();
+            if let [.., control, end] = tributary.points.as_mut_slice() {
+                *control += offset;
+                *end = confluence;
+            }
+
+            let confluence_node = nodes.len();
+            nodes.push(RiverNode {
+                position: confluence,
+                kind: RiverNodeKind::Confluence,
+            });
+            let source_node = nodes.len();
+            nodes.push(RiverNode {
+                position: tributary.starting_point(),
+                kind: RiverNodeKind::Source,
+            });
+
+            edges.push(Self::main_edge(
+                &main_river,
+                (upstream_node, confluence_node),
+                (upstream_t, confluence_t),
+                tributary_area,
+            ));
+            tributary_area += tributary.width().mouth * tributary.width().mouth;
+            edges.push(RiverEdge {
+                from: source_node,
+                to: confluence_node,
+                width: tributary.width().mouth,
+                river: tributary,
+            });
+
+            upstream_node = confluence_node;
+            upstream_t = confluence_t;
+        }
+
+        let mouth_node = nodes.len();
+        nodes.push(RiverNode {
+            position: main_river.ending_point(),
+            kind: RiverNodeKind::Mouth,
+        });
+        edges.push(Self::main_edge(
+            &main_river,
+            (upstream_node, mouth_node),
+            (upstream_t, 1.0),
+            tributary_area,
+        ));
+
+        Self { nodes, edges }
+    }
+
+    /// The stretch of the main river between two nodes, widened to also carry the tributaries
+    /// that joined upstream. Both ends of the widened river keep the cross section area of all of
+    /// them.
+    fn main_edge(
+        main_river: &River,
+        (from, to): (usize, usize),
+        (start, end): (f32, f32),
+        tributary_area: f32,
+    ) -> RiverEdge {
+        let mut river = main_river.slice(start, end);
+        let width = river.width_mut();
+        width.source = (width.source * width.source + tributary_area).sqrt();
+        width.mouth = (width.mouth * width.mouth + tributary_area).sqrt();
+
+        RiverEdge {
+            from,
+            to,
+            width: river.width().mouth,
+            river,
+        }
+    }
+
+    fn river_width(config: &RiverConfig, size: f32, rng: &mut ChaCha8Rng) -> RiverWidth {
+        RiverWidth::new(
+            size * config.source_width_ratio,
+            size,
+            config.width_noise,
+            rng.gen(),
+        )
+    }
+
+    /// A random main river, with tributaries coming in from the side at random points along it.
+    /// When there is an ocean the main river runs from a random land cell to a random point on the
+    /// coast, otherwise it connects two map edges
+    fn random_rivers(
+        config: &RiverConfig,
+        heightmap: &Heightmap,
+        ocean: &OceanMap,
+        rng: &mut ChaCha8Rng,
+    ) -> (River, Vec<(f32, River)>) {
+        let terrain_size = heightmap.world_size();
+        let main_width = Self::river_width(config, config.size, rng);
+
+        let coast_cells = ocean.coast_cells();
+        let land_cells = (0..heightmap.heights().len())
+            .filter(|i| !ocean.is_ocean(*i))
+            .collect::<Vec<_>>();
+        let mut main_river = if coast_cells.is_empty() || land_cells.is_empty() {
+            River::new(terrain_size, main_width, config.segment_count, rng.gen())
+        } else {
+            let source = land_cells[rng.gen_range(0..land_cells.len())];
+            let mouth = coast_cells[rng.gen_range(0..coast_cells.len())];
+            River::between(
+                Self::cell_position(heightmap, source),
+                Self::cell_position(heightmap, mouth),
+                main_width,
+                config.segment_count,
+                rng.gen(),
+            )
+        };
+        main_river.random_shift(config.shift_iterations);
+
+        let tributaries = (0..config.tributary_count)
+            .map(|_| {
+                let confluence_t = rng.gen_range(Self::CONFLUENCE_RANGE);
+                let confluence = main_river.evaluate(confluence_t);
+                let tangent = (main_river.evaluate(confluence_t + 0.01)
+                    - main_river.evaluate(confluence_t - 0.01))
+                .normalize();
+                let side = if rng.gen_bool(0.5) { 1.0 } else { -1.0 };
+                let normal = Vector2::new(-tangent.y, tangent.x) * side;
+
+                // Tributaries come from upstream at an angle, like they would in a valley
+                let length = rng.gen_range(0.15..0.3) * terrain_size;
+                let source = (confluence + (normal * 0.8 - tangent * 0.6).normalize() * length)
+                    .map(|component| component.clamp(0.0, terrain_size));
+                let midpoint = PerlinNoise::lerp(source, confluence, 0.5)
+                    + normal * rng.gen_range(-0.1..0.1) * length;
+
+                let tributary_width = Self::river_width(config, config.tributary_size, rng);
+                let tributary =
+                    River::from_knots(&[source, midpoint, confluence], tributary_width, rng.gen());
+                (confluence_t, tributary)
+            })
+            .collect();
+
+        (main_river, tributaries)
+    }
+
+    /// A downhill main river, with tributaries traced along the drainage from other high cells
+    /// until they run into it. Tributaries never share cells with each other, so every confluence
+    /// lies on the main river.
+    fn downhill_rivers(
+        config: &RiverConfig,
+        heightmap: &Heightmap,
+        ocean: &OceanMap,
+        rng: &mut ChaCha8Rng,
+    ) -> (River, Vec<(f32, River)>) {
+        let drainage = hydrology::priority_flood(heightmap, ocean);
+        let main_width = Self::river_width(config, config.size, rng);
+        let main_river = River::downhill(heightmap, &drainage, main_width, rng.gen());
+
+        let main_path =
+            drainage.flow_path(Self::nearest_cell(heightmap, main_river.starting_point()));
+        let main_cells = main_path
+            .iter()
+            .enumerate()
+            .map(|(i, cell)| (*cell, i))
+            .collect::<HashMap<_, _>>();
+
+        let source_candidates =
+            River::source_candidates(heightmap, Self::TRIBUTARY_SOURCE_QUANTILE);
+        let mut claimed_cells = HashSet::new();
+        let mut confluence_indices: Vec<usize> = Vec::new();
+        let mut tributaries = Vec::new();
+
+        for _ in 0..config.tributary_count * Self::TRIBUTARY_ATTEMPTS_PER_TRIBUTARY {
+            if tributaries.len() == config.tributary_count || source_candidates.is_empty() {
+                break;
+            }
+            let source = source_candidates[rng.gen_range(0..source_candidates.len())];
+
+            let mut path = vec![source];
+            let mut current = source;
+            let mut confluence_index = None;
+            while let Some(next) = drainage.downstream[current] {
+                path.push(next);
+                if let Some(index) = main_cells.get(&next) {
+                    confluence_index = Some(*index);
+                    break;
+                }
+                current = next;
+            }
+
+            let Some(confluence_index) = confluence_index else {
+                continue;
+            };
+            if path.len() < Self::MIN_TRIBUTARY_CELLS
+                || path[..path.len() - 1]
+                    .iter()
+                    .any(|cell| main_cells.contains_key(cell) || claimed_cells.contains(cell))
+                || confluence_indices
+                    .iter()
+                    .any(|index| index.abs_diff(confluence_index) < Self::MIN_TRIBUTARY_CELLS)
+            {
+                continue;
+            }
+
+            claimed_cells.extend(path[..path.len() - 1].iter().copied());
+            confluence_indices.push(confluence_index);
+
+            let tributary_width = Self::river_width(config, config.tributary_size, rng);
+            let tributary = River::from_path(heightmap, &path, tributary_width, rng.gen());
+            let (confluence_t, _) = main_river.closest_point(tributary.ending_point());
+            tributaries.push((confluence_t, tributary));
+        }
+
+        (main_river, tributaries)
+    }
+
+    fn cell_position(heightmap: &Heightmap, index: usize) -> Vector2<f32> {
+        let resolution = heightmap.resolution();
+        heightmap.cell_position(index as u32 % resolution, index as u32 / resolution)
+    }
+
+    fn nearest_cell(heightmap: &Heightmap, position: Vector2<f32>) -> usize {
+        let resolution = heightmap.resolution();
+        let cell = heightmap.position_to_cell(position);
+        let x = (cell.x.round().max(0.0) as u32).min(resolution - 1);
+        let y = (cell.y.round().max(0.0) as u32).min(resolution - 1);
+        (y * resolution + x) as usize
+    }
+
+    pub fn rivers(&self) -> impl Iterator<Item = &River> {
+        self.edges.iter().map(|edge| &edge.river)
+    }
+
+    /// Whether an edge is a stretch of the main river rather than a tributary. The main river
+    /// starts at the first node and every later stretch of it starts at a confluence.
+    pub fn is_main_edge(&self, edge: &RiverEdge) -> bool {
+        edge.from == 0 || self.nodes[edge.from].kind == RiverNodeKind::Confluence
+    }
+
+    /// Lowers the heightmap along every river in the network, taking the water level from
+    /// `water_surface`. The stretches of the main river are carved in order from source to
+    /// mouth, each starting no higher than where the one upstream ended, so its bed never steps
+    /// back up at a confluence.
+    pub fn carve(
+        &self,
+        heightmap: &mut Heightmap,
+        water_surface: &Heightmap,
+        depth: f32,
+        bank_width: f32,
+    ) {
+        let mut main_level = f32::INFINITY;
+        for edge in &self.edges {
+            let river = &edge.river;
+            if self.is_main_edge(edge) {
+                main_level =
+                    river.carve_below(heightmap, water_surface, depth, bank_width, main_level);
+            } else {
+                river.carve(heightmap, water_surface, depth, bank_width);
+            }
+        }
+    }
+}
+
+/// Generates `config.count` river networks over the heightmap, each seeded with `seed` offset by
+/// its index
+pub fn networks_from_config(
+    config: &RiverConfig,
+    heightmap: &Heightmap,
+    ocean: &OceanMap,
+    seed: u64,
+) -> Vec<RiverNetwork> {
+    (0..config.count)
+        .map(|i| RiverNetwork::new(config, heightmap, ocean, seed.wrapping_add(i as u64)))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn test_config(mode: RiverMode, tributary_count: usize) -> RiverConfig {
+        RiverConfig {
+            mode,
+            segment_count: 4,
+            tributary_count,
+            ..Default::default()
+        }
+    }
+
+    fn network(config: &RiverConfig, heightmap: &Heightmap, seed: u64) -> RiverNetwork {
+        // Sea level below the whole terrain, so there is no ocean and rivers run to the map edge
+        let ocean = OceanMap::new(heightmap, -1.0);
+        RiverNetwork::new(config, heightmap, &ocean, seed)
+    }
+
+    fn count(network: &RiverNetwork, kind: RiverNodeKind) -> usize {
+        network
+            .nodes
+            .iter()
+            .filter(|node| node.kind == kind)
+            .count()
+    }
+
+    /// Every source feeds one edge, every confluence joins two edges into one, the mouth ends
+    /// the last edge, and every edge runs between the positions of its nodes
+    fn assert_topology(network: &RiverNetwork) {
+        for (i, node) in network.nodes.iter().enumerate() {
+            let incoming = network.edges.iter().filter(|edge| edge.to == i).count();
+            let outgoing = network.edges.iter().filter(|edge| edge.from == i).count();
+            let expected = match node.kind {
+                RiverNodeKind::Source => (0, 1),
+                RiverNodeKind::Confluence => (2, 1),
+                RiverNodeKind::Mouth => (1, 0),
+            };
+            assert_eq!(
+                (incoming, outgoing),
+                expected,
+                "node {i} is a {:?}",
+                node.kind
+            );
+        }
+        assert_eq!(count(network, RiverNodeKind::Mouth), 1);
+        assert_eq!(network.edges.len(), network.nodes.len() - 1);
+
+        for edge in &network.edges {
+            let from = network.nodes[edge.from].position;
+            let to = network.nodes[edge.to].position;
+            assert!((edge.river.starting_point() - from).norm() < 1e-3);
+            assert!((edge.river.ending_point() - to).norm() < 1e-3);
+        }
+    }
+
+    #[test]
+    fn random_network_has_a_confluence_per_tributary() {
+        let heightmap = Heightmap::from_fn(34, 32.0, |_, _| 0.5);
+        for seed in 0..4 {
+            let network = network(&test_config(RiverMode::Random, 3), &heightmap, seed);
+
+            assert_eq!(count(&network, RiverNodeKind::Source), 4);
+            assert_eq!(count(&network, RiverNodeKind::Confluence), 3);
+            assert_eq!(network.edges.len(), 7);
+            assert_topology(&network);
+        }
+    }
+
+    #[test]
+    fn main_river_widens_at_every_confluence() {
+        let heightmap = Heightmap::from_fn(34, 32.0, |_, _| 0.5);
+        for source_width_ratio in [1.0, 0.4] {
+            let config = RiverConfig {
+                source_width_ratio,
+                ..test_config(RiverMode::Random, 3)
+            };
+            let network = network(&config, &heightmap, 5);
+
+            let mut main_width = None;
+            let mut tributary_area = 0.0;
+            for edge in network
+                .edges
+                .iter()
+                .filter(|edge| network.is_main_edge(edge))
+            {
+                let Some(upstream_width) = main_width else {
+                    main_width = Some(edge.width);
+                    continue;
+                };
+                let tributary = network
+                    .edges
+                    .iter()
+                    .find(|other| other.to == edge.from && !network.is_main_edge(other))
+                    .unwrap();
+                tributary_area += tributary.width * tributary.width;
+
+                // The stretch below a confluence carries the cross section area of both rivers
+                let expected: f32 =
+                    upstream_width * upstream_width + tributary.width * tributary.width;
+                assert!((edge.river.width().source - expected.sqrt()).abs() < 1e-4);
+                assert!(edge.width > upstream_width);
+                main_width = Some(edge.width);
+            }
+
+            // The mouth carries the main river at full size and every tributary
+            let mouth = network.edges.last().unwrap();
+            assert_eq!(network.nodes[mouth.to].kind, RiverNodeKind::Mouth);
+            let expected: f32 = config.size * config.size + tributary_area;
+            assert!((mouth.width - expected.sqrt()).abs() < 1e-4);
+        }
+    }
+
+    #[test]
+    fn downhill_tributaries_join_the_main_river() {
+        let perlin = PerlinNoise::new(4, 0.5, 3);
+        let heightmap = Heightmap::from_perlin(&perlin, 8, 96, 96.0);
+        let mut confluence_count = 0;
+        for seed in 0..4 {
+            let network = network(&test_config(RiverMode::Downhill, 2), &heightmap, seed);
+            confluence_count += count(&network, RiverNodeKind::Confluence);
+
+            assert_topology(&network);
+            let main_river = network
+                .edges
+                .iter()
+                .filter(|edge| network.is_main_edge(edge))
+                .collect::<Vec<_>>();
+            for node in network
+                .nodes
+                .iter()
+                .filter(|node| node.kind == RiverNodeKind::Confluence)
+            {
+                assert!(main_river
+                    .iter()
+                    .any(|edge| edge.river.closest_point(node.position).1 < 1e-3));
+            }
+        }
+        // Not every seed finds room for its tributaries, but some do
+        assert!(confluence_count > 0);
+    }
+}
@@ -0,0 +1,202 @@
+use nalgebra::Vector2;
+
+use crate::noise::{lattice_hash, Noise2D};
+
+#[derive(Debug, Clone)]
+/// Ken Perlin's simplex noise over a triangular lattice, with the gradients picked by hashing
+/// the lattice point. Cheaper than Perlin noise and free of its axis aligned artifacts
+pub struct SimplexNoise {
+    seed: u64,
+}
+
+impl SimplexNoise {
+    /// `(sqrt(3) - 1) / 2`, skews the input onto the square lattice
+    const SKEW: f32 = 0.366_025_4;
+    /// `(3 - sqrt(3)) / 6`, unskews a lattice point back to the input space
+    const UNSKEW: f32 = 0.211_324_87;
+    const GRADIENTS: [(f32, f32); 12] = [
+        (1.0, 1.0),
+        (-1.0, 1.0),
+        (1.0, -1.0),
+        (-1.0, -1.0),
+        (1.0, 0.0),
+        (-1.0, 0.0),
+        (1.0, 0.0),
+        (-1.0, 0.0),
+        (0.0, 1.0),
+        (0.0, -1.0),
+        (0.0, 1.0),
+        (0.0, -1.0),
+    ];
+    /// Scales the sum of the corner contributions to `[-1, 1]`
+    const NORMALIZER: f32 = 70.0;
+
+    pub fn new(seed: u64) -> Self {
+        Self { seed }
+    }
+
+    fn corner(&self, x: i32, y: i32, offset: Vector2<f32>) -> f32 {
+        let falloff = 0.5 - offset.norm_squared();
+        if falloff <= 0.0 {
+            return 0.0;
+        }
+
+        let (gradient_x, gradient_y) = Self::GRADIENTS
+            [(lattice_hash(self.seed, x, y) % Self::GRADIENTS.len() as u64) as usize];
+        falloff.powi(4) * (gradient_x * offset.x + gradient_y * offset.y)
+    }
+}
+
+impl Noise2D for SimplexNoise {
+    fn sample(&self, x: f32, y: f32) -> f32 {
+        let skew = (x + y) * Self::SKEW;
+        let cell_x = (x + skew).floor();
+        let cell_y = (y + skew).floor();
+
+        let unskew = (cell_x + cell_y) * Self::UNSKEW;
+        let offset = Vector2::new(x - (cell_x - unskew), y - (cell_y - unskew));
+
+        // Which of the two triangles of the skewed square the point is in
+        let middle_corner = if offset.x > offset.y {
+            Vector2::new(1.0, 0.0)
+        } else {
+            Vector2::new(0.0, 1.0)
+        };
+
+        let cell_x = cell_x as i32;
+        let cell_y = cell_y as i32;
+        let middle_offset = offset - middle_corner + Vector2::repeat(Self::UNSKEW);
+        let far_offset = offset - Vector2::repeat(1.0 - 2.0 * Self::UNSKEW);
+
+        Self::NORMALIZER
+            * (self.corner(cell_x, cell_y, offset)
+                + self.corner(
+                    cell_x + middle_corner.x as i32,
+                    cell_y + middle_corner.y as i32,
+                    middle_offset,
+                )
+                + self.corner(cell_x + 1, cell_y + 1, far_offset))
+    }
+}
+
+#[derive(Debug, Clone)]
+/// OpenSimplex2 noise, following KdotJPG's 2D variant. The lattice is the same as simplex noise,
+/// but the gradients are 24 evenly spread directions that avoid lining up with the lattice, which
+/// removes the faint diagonal streaks simplex noise can show
+pub struct OpenSimplex2Noise {
+    seed: u64,
+    gradients: [Vector2<f32>; 24],
+}
+
+impl OpenSimplex2Noise {
+    const SKEW: f32 = 0.366_025_4;
+    const UNSKEW: f32 = -0.211_324_87;
+    const RADIUS_SQUARED: f32 = 0.5;
+    /// Scales the sum of the corner contributions to `[-1, 1]`
+    const NORMALIZER: f32 = 1.0 / 0.010_016_341;
+
+    pub fn new(seed: u64) -> Self {
+        let gradients = std::array::from_fn(|i| {
+            let angle = (7.5 + 15.0 * i as f32).to_radians();
+            Vector2::new(angle.cos(), angle.sin())
+        });
+
+        Self { seed, gradients }
+    }
+
+    fn corner(&self, x: i32, y: i32, offset: Vector2<f32>) -> f32 {
+        let falloff = Self::RADIUS_SQUARED - offset.norm_squared();
+        if falloff <= 0.0 {
+            return 0.0;
+        }
+
+        let gradient =
+            self.gradients[(lattice_hash(self.seed, x, y) % self.gradients.len() as u64) as usize];
+        falloff.powi(4) * gradient.dot(&offset)
+    }
+}
+
+impl Noise2D for OpenSimplex2Noise {
+    fn sample(&self, x: f32, y: f32) -> f32 {
+        let skew = (x + y) * Self::SKEW;
+        let skewed_x = x + skew;
+        let skewed_y = y + skew;
+
+        let base_x = skewed_x.floor();
+        let base_y = skewed_y.floor();
+        let inner_x = skewed_x - base_x;
+        let inner_y = skewed_y - base_y;
+        let base_x = base_x as i32;
+        let base_y = base_y as i32;
+
+        let unskew = (inner_x + inner_y) * Self::UNSKEW;
+        let offset = Vector2::new(inner_x + unskew, inner_y + unskew);
+
+        let mut value = self.corner(base_x, base_y, offset);
+        value += self.corner(
+            base_x + 1,
+            base_y + 1,
+            offset - Vector2::repeat(1.0 + 2.0 * Self::UNSKEW),
+        );
+        value += if offset.y > offset.x {
+            self.corner(
+                base_x,
+                base_y + 1,
+                offset - Vector2::new(Self::UNSKEW, Self::UNSKEW + 1.0),
+            )
+        } else {
+            self.corner(
+                base_x + 1,
+                base_y,
+                offset - Vector2::new(Self::UNSKEW + 1.0, Self::UNSKEW),
+            )
+        };
+
+        value * Self::NORMALIZER
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn samples() -> impl Iterator<Item = (f32, f32)> {
+        (0..1600).map(|i| ((i % 40) as f32 * 0.37 - 7.0, (i / 40) as f32 * 0.53 - 9.0))
+    }
+
+    fn check_seeds(first: &dyn Noise2D, second: &dyn Noise2D, other: &dyn Noise2D) {
+        assert!(samples().all(|(x, y)| first.sample(x, y) == second.sample(x, y)));
+        assert!(samples().any(|(x, y)| first.sample(x, y) != other.sample(x, y)));
+    }
+
+    #[test]
+    fn same_seed_gives_the_same_noise() {
+        check_seeds(
+            &SimplexNoise::new(9),
+            &SimplexNoise::new(9),
+            &SimplexNoise::new(10),
+        );
+        check_seeds(
+            &OpenSimplex2Noise::new(9),
+            &OpenSimplex2Noise::new(9),
+            &OpenSimplex2Noise::new(10),
+        );
+    }
+
+    #[test]
+    fn noise_stays_in_range() {
+        let noises: [Box<dyn Noise2D>; 2] = [
+            Box::new(SimplexNoise::new(4)),
+            Box::new(OpenSimplex2Noise::new(4)),
+        ];
+        for noise in noises {
+            let values = samples()
+                .map(|(x, y)| noise.sample(x, y))
+                .collect::<Vec<_>>();
+            assert!(values.iter().all(|value| (-1.0..=1.0).contains(value)));
+            // Spread over most of the range rather than stuck near zero
+            assert!(values.iter().any(|value| *value > 0.5));
+            assert!(values.iter().any(|value| *value < -0.5));
+        }
+    }
+}
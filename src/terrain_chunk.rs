@@ -0,0 +1,306 @@
+use std::collections::HashMap;
+
+use nalgebra::Vector2;
+use rayon::prelude::*;
+
+use crate::{
+    heightmap::Heightmap,
+    noise::Noise2D,
+    terrain_normals::TerrainNormals,
+    world_config::{ChunkConfig, TerrainConfig},
+};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+/// Position of a chunk on the chunk grid. Chunk `(0, 0)` starts at the world origin
+pub struct ChunkCoord {
+    pub x: i32,
+    pub y: i32,
+}
+
+impl ChunkCoord {
+    pub fn new(x: i32, y: i32) -> Self {
+        Self { x, y }
+    }
+
+    /// The chunk containing a world space position on the ground plane
+    pub fn containing(position: Vector2<f32>, chunk_size: f32) -> Self {
+        Self {
+            x: (position.x / chunk_size).floor() as i32,
+            y: (position.y / chunk_size).floor() as i32,
+        }
+    }
+
+    /// World space position of the corner of the chunk with the smallest coordinates
+    pub fn origin(&self, chunk_size: f32) -> Vector2<f32> {
+        Vector2::new(self.x as f32, self.y as f32) * chunk_size
+    }
+
+    /// Number of chunks between the two along the axis where they are furthest apart
+    pub fn distance(&self, other: ChunkCoord) -> usize {
+        (self.x - other.x)
+            .unsigned_abs()
+            .max((self.y - other.y).unsigned_abs()) as usize
+    }
+}
+
+#[derive(Debug, Clone)]
+/// The heights of one chunk. Like the single terrain heightmap, it has a one cell border on every
+/// side, and that border holds the same heights as the edge of the neighbouring chunks, so
+/// neighbouring meshes meet without seams. The normals are found from heights sampled one cell
+/// further out, so they match along the shared edges too
+pub struct TerrainChunk {
+    pub coord: ChunkCoord,
+    pub heightmap: Heightmap,
+    pub normals: TerrainNormals,
+}
+
+impl TerrainChunk {
+    /// Samples the noise over the chunk. `noise_scale` is the number of noise units per world
+    /// unit, so that chunks keep the scale of the single terrain
+    pub fn new(
+        coord: ChunkCoord,
+        noise: &(impl Noise2D + ?Sized),
+        noise_scale: f32,
+        config: &ChunkConfig,
+    ) -> Self {
+        let quad_size = config.size / config.resolution as f32;
+        let origin = coord.origin(config.size);
+
+        // Sampled with a two cell border, so cell 2 sits on the chunk origin and every cell kept
+        // after cropping has a neighbour on each side for its normal
+        let sampled = Heightmap::from_fn(
+            config.resolution as u32 + 4,
+            config.size + 2.0 * quad_size,
+            |x, y| {
+                let position = origin + Vector2::new(x as f32 - 2.0, y as f32 - 2.0) * quad_size;
+                let noise_val = noise.sample(position.x * noise_scale, position.y * noise_scale);
+                (noise_val + 1.0) / 2.0
+            },
+        );
+
+        let normals = TerrainNormals::new(&sampled).cropped(1);
+        let heightmap = sampled.cropped(1);
+
+        Self {
+            coord,
+            heightmap,
+            normals,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+/// Chunks that started or stopped being drawn after the camera moved
+pub struct ChunkChanges {
+    pub shown: Vec<ChunkCoord>,
+    pub hidden: Vec<ChunkCoord>,
+}
+
+/// Generates chunks around the camera and drops them once it moves far enough away. The drawn
+/// chunks always form a square of `2 * load_radius + 1` chunks per side centered on the camera
+pub struct ChunkStreamer {
+    noise: Box<dyn Noise2D>,
+    noise_scale: f32,
+    config: ChunkConfig,
+    chunks: HashMap<ChunkCoord, TerrainChunk>,
+    center: Option<ChunkCoord>,
+}
+
+impl std::fmt::Debug for ChunkStreamer {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("ChunkStreamer")
+            .field("noise_scale", &self.noise_scale)
+            .field("config", &self.config)
+            .field("chunks", &self.chunks.len())
+            .field("center", &self.center)
+            .finish()
+    }
+}
+
+impl ChunkStreamer {
+    pub fn new(noise: Box<dyn Noise2D>, terrain: &TerrainConfig, config: &ChunkConfig) -> Self {
+        Self {
+            noise,
+            noise_scale: terrain.perlin_size as f32 / terrain.size,
+            config: config.clone(),
+            chunks: HashMap::new(),
+            center: None,
+        }
+    }
+
+    pub fn config(&self) -> &ChunkConfig {
+        &self.config
+    }
+
+    /// Number of chunks drawn at once
+    pub fn visible_count(&self) -> usize {
+        (2 * self.config.load_radius + 1).pow(2)
+    }
+
+    /// The chunk the camera was in at the last update
+    pub fn center(&self) -> Option<ChunkCoord> {
+        self.center
+    }
+
+    pub fn chunk(&self, coord: ChunkCoord) -> Option<&TerrainChunk> {
+        self.chunks.get(&coord)
+    }
+
+    /// Coordinates of the chunks drawn around `center`
+    pub fn visible_around(&self, center: ChunkCoord) -> Vec<ChunkCoord> {
+        let radius = self.config.load_radius as i32;
+        (-radius..=radius)
+            .flat_map(|y| {
+                (-radius..=radius).map(move |x| ChunkCoord::new(center.x + x, center.y + y))
+            })
+            .collect()
+    }
+
+    /// Moves the streamed area to the camera, generating the chunks that came into range in
+    /// parallel and dropping the cached ones that fell out of it
+    pub fn update(&mut self, camera_position: Vector2<f32>) -> ChunkChanges {
+        let center = ChunkCoord::containing(camera_position, self.config.size);
+        if self.center == Some(center) {
+            return ChunkChanges::default();
+        }
+
+        let previous = self
+            .center
+            .map(|previous| self.visible_around(previous))
+            .unwrap_or_default();
+        let visible = self.visible_around(center);
+
+        let missing = visible
+            .iter()
+            .filter(|coord| !self.chunks.contains_key(coord))
+            .copied()
+            .collect::<Vec<_>>();
+        let generated = missing
+            .par_iter()
+            .map(|coord| TerrainChunk::new(*coord, &self.noise, self.noise_scale, &self.config))
+            .collect::<Vec<_>>();
+        self.chunks
+            .extend(generated.into_iter().map(|chunk| (chunk.coord, chunk)));
+
+        let unload_radius = self.config.unload_radius;
+        self.chunks
+            .retain(|coord, _| coord.distance(center) <= unload_radius);
+
+        self.center = Some(center);
+
+        ChunkChanges {
+            shown: visible
+                .iter()
+                .filter(|coord| !previous.contains(coord))
+                .copied()
+                .collect(),
+            hidden: previous
+                .into_iter()
+                .filter(|coord| !visible.contains(coord))
+                .collect(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::perlin_noise::PerlinNoise;
+
+    fn test_config() -> ChunkConfig {
+        ChunkConfig {
+            size: 4.0,
+            resolution: 16,
+            load_radius: 1,
+            unload_radius: 2,
+            ..Default::default()
+        }
+    }
+
+    fn streamer(config: &ChunkConfig) -> ChunkStreamer {
+        let terrain = TerrainConfig::default();
+        ChunkStreamer::new(Box::new(PerlinNoise::new(4, 0.5, 1)), &terrain, config)
+    }
+
+    fn sorted(mut coords: Vec<ChunkCoord>) -> Vec<(i32, i32)> {
+        coords.sort_by_key(|coord| (coord.x, coord.y));
+        coords.into_iter().map(|coord| (coord.x, coord.y)).collect()
+    }
+
+    #[test]
+    fn crossing_a_chunk_border_shows_and_hides_a_column() {
+        let config = test_config();
+        let mut streamer = streamer(&config);
+
+        let first = streamer.update(Vector2::new(2.0, 2.0));
+        assert_eq!(first.shown.len(), streamer.visible_count());
+        assert!(first.hidden.is_empty());
+
+        // Moving within the chunk changes nothing
+        let same = streamer.update(Vector2::new(3.5, 0.5));
+        assert!(same.shown.is_empty() && same.hidden.is_empty());
+
+        // One chunk to the right, the column at x = 2 comes in and the one at x = -1 goes
+        let moved = streamer.update(Vector2::new(4.5, 2.0));
+        assert_eq!(streamer.center(), Some(ChunkCoord::new(1, 0)));
+        assert_eq!(sorted(moved.shown), vec![(2, -1), (2, 0), (2, 1)]);
+        assert_eq!(sorted(moved.hidden), vec![(-1, -1), (-1, 0), (-1, 1)]);
+        for coord in streamer.visible_around(ChunkCoord::new(1, 0)) {
+            assert!(streamer.chunk(coord).is_some());
+        }
+    }
+
+    #[test]
+    fn chunks_are_cached_until_beyond_the_unload_radius() {
+        let config = test_config();
+        let mut streamer = streamer(&config);
+
+        streamer.update(Vector2::new(2.0, 2.0));
+        streamer.update(Vector2::new(6.0, 2.0));
+        // Chunk (-1, 0) is hidden but within two chunks of the camera, so it stays cached
+        assert!(streamer.chunk(ChunkCoord::new(-1, 0)).is_some());
+
+        streamer.update(Vector2::new(10.0, 2.0));
+        assert!(streamer.chunk(ChunkCoord::new(-1, 0)).is_none());
+        assert!(streamer.chunk(ChunkCoord::new(0, 0)).is_some());
+
+        // Turning back shows the cached chunk again
+        let back = streamer.update(Vector2::new(6.0, 2.0));
+        assert_eq!(sorted(back.shown), vec![(0, -1), (0, 0), (0, 1)]);
+    }
+
+    #[test]
+    fn neighbouring_chunks_share_edge_heights() {
+        let config = test_config();
+        let noise = PerlinNoise::new(4, 0.5, 1);
+        let chunk = TerrainChunk::new(ChunkCoord::new(0, 0), &noise, 1.5, &config);
+        let right = TerrainChunk::new(ChunkCoord::new(1, 0), &noise, 1.5, &config);
+        let below = TerrainChunk::new(ChunkCoord::new(0, 1), &noise, 1.5, &config);
+
+        // The border column before the first vertex of a chunk is the column before the last
+        // vertex of the previous chunk, and the last vertex column is the next chunk's first
+        let edge = config.resolution as u32 + 1;
+        for i in 0..=edge {
+            assert_eq!(chunk.heightmap.get(edge, i), right.heightmap.get(1, i));
+            assert_eq!(chunk.heightmap.get(edge - 1, i), right.heightmap.get(0, i));
+            assert_eq!(chunk.heightmap.get(i, edge), below.heightmap.get(i, 1));
+            assert_eq!(chunk.heightmap.get(i, edge - 1), below.heightmap.get(i, 0));
+        }
+    }
+
+    #[test]
+    fn neighbouring_chunks_share_edge_normals() {
+        let config = test_config();
+        let noise = PerlinNoise::new(4, 0.5, 1);
+        let chunk = TerrainChunk::new(ChunkCoord::new(0, 0), &noise, 1.5, &config);
+        let right = TerrainChunk::new(ChunkCoord::new(1, 0), &noise, 1.5, &config);
+        let below = TerrainChunk::new(ChunkCoord::new(0, 1), &noise, 1.5, &config);
+
+        // The last vertex column of a chunk is the first vertex column of the next one
+        let edge = config.resolution as u32 + 1;
+        for i in 1..=edge {
+            assert!((chunk.normals.normal(edge, i) - right.normals.normal(1, i)).norm() < 1e-5);
+            assert!((chunk.normals.normal(i, edge) - below.normals.normal(i, 1)).norm() < 1e-5);
+        }
+    }
+}
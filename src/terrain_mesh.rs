@@ -0,0 +1,259 @@
+use gamezap::model::Vertex;
+
+use crate::world_config::ChunkConfig;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+/// Order of the corners of every triangle, seen from above
+pub enum Winding {
+    #[default]
+    Clockwise,
+    CounterClockwise,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+/// Which diagonal splits every quad into two triangles
+pub enum Diagonal {
+    /// Every quad is split from its first corner to the opposite one
+    #[default]
+    Uniform,
+    /// The diagonal flips from one quad to the next in a checkerboard, so slopes do not all lean
+    /// the same way
+    Alternating,
+}
+
+/// Creates a 2-dimensional grid of shared vertices.
+/// Specify the resolution of the grid (number of quads of the finest level),
+/// the size of every quad of the mesh, and the number of quads merged into one along each side.
+/// The final mesh is of size `(resolution * quad_size)^2`, and its texture coordinates always
+/// count quads of the finest level so that coarser grids sample the same height texture.
+/// Vertices are stored row by row, `(resolution / step + 1)^2` of them
+pub fn grid_mesh(
+    resolution: usize,
+    quad_size: f32,
+    step: usize,
+    winding: Winding,
+    diagonal: Diagonal,
+) -> (Vec<Vertex>, Vec<u32>) {
+    let cells = resolution / step;
+    let cell_size = quad_size * step as f32;
+    let row_length = cells + 1;
+
+    let vertices = (0..row_length)
+        .flat_map(|i| {
+            (0..row_length).map(move |j| Vertex {
+                position: [j as f32 * cell_size, 0.0, i as f32 * cell_size],
+                tex_coords: [(j * step) as f32, (i * step) as f32],
+                normal: [0.0, 1.0, 0.0],
+            })
+        })
+        .collect();
+
+    let indices = (0..cells)
+        .flat_map(|i| (0..cells).map(move |j| (i, j)))
+        .flat_map(|(i, j)| {
+            let top_left = (i * row_length + j) as u32;
+            let top_right = top_left + 1;
+            let bottom_left = top_left + row_length as u32;
+            let bottom_right = bottom_left + 1;
+
+            let flipped = diagonal == Diagonal::Alternating && (i + j) % 2 == 1;
+            let triangles = if flipped {
+                [
+                    [top_left, top_right, bottom_left],
+                    [top_right, bottom_right, bottom_left],
+                ]
+            } else {
+                [
+                    [top_left, top_right, bottom_right],
+                    [top_left, bottom_right, bottom_left],
+                ]
+            };
+
+            triangles
+                .into_iter()
+                .flat_map(move |[a, b, c]| match winding {
+                    Winding::Clockwise => [a, b, c],
+                    Winding::CounterClockwise => [a, c, b],
+                })
+        })
+        .collect();
+
+    (vertices, indices)
+}
+
+/// Hangs a vertical strip of `depth` world units below every edge of a grid made by
+/// [`grid_mesh`] with the same `resolution` and `step`. Where a chunk meets a
+/// coarser neighbour, the edges do not share every vertex and the heights between them disagree,
+/// and the skirt fills the gap that would open there. The strips are drawn from both sides, so
+/// the winding does not depend on the edge
+pub fn add_skirt(mesh: &mut (Vec<Vertex>, Vec<u32>), resolution: usize, step: usize, depth: f32) {
+    let (vertices, indices) = mesh;
+    let row_length = resolution / step + 1;
+    let last = row_length - 1;
+
+    let edges: [Vec<usize>; 4] = [
+        (0..row_length).collect(),
+        (0..row_length).map(|t| t * row_length + last).collect(),
+        (0..row_length).map(|t| last * row_length + t).collect(),
+        (0..row_length).map(|t| t * row_length).collect(),
+    ];
+
+    for edge in edges {
+        let first_bottom = vertices.len() as u32;
+        let bottom_vertices = edge
+            .iter()
+            .map(|top| {
+                let mut bottom = vertices[*top];
+                bottom.position[1] -= depth;
+                bottom
+            })
+            .collect::<Vec<_>>();
+        vertices.extend(bottom_vertices);
+
+        for t in 0..last {
+            let top = [edge[t] as u32, edge[t + 1] as u32];
+            let bottom = [first_bottom + t as u32, first_bottom + t as u32 + 1];
+            indices.extend([top[0], top[1], bottom[1], top[0], bottom[1], bottom[0]]);
+            indices.extend([top[0], bottom[1], top[1], top[0], bottom[0], bottom[1]]);
+        }
+    }
+}
+
+/// Level of detail of a chunk `distance` chunks away from the camera. Every level halves the
+/// number of quads along each side of the chunk
+pub fn lod_level(distance: usize, config: &ChunkConfig) -> u32 {
+    ((distance / config.lod_ring_width) as u32).min(config.lod_levels)
+}
+
+/// Mesh of a streamed chunk at a level of detail, with a skirt to hide the cracks against
+/// neighbours of a different level
+pub fn chunk_mesh(config: &ChunkConfig, lod: u32) -> (Vec<Vertex>, Vec<u32>) {
+    let quad_size = config.size / config.resolution as f32;
+    let step = 1 << lod;
+
+    let mut mesh = grid_mesh(
+        config.resolution,
+        quad_size,
+        step,
+        Winding::default(),
+        Diagonal::Alternating,
+    );
+    if config.skirt_depth > 0.0 {
+        add_skirt(&mut mesh, config.resolution, step, config.skirt_depth);
+    }
+    mesh
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Normal of a triangle, which points up for counter-clockwise triangles seen from above
+    fn triangle_normal(vertices: &[Vertex], triangle: &[u32]) -> f32 {
+        let [a, b, c] = [0, 1, 2].map(|i| vertices[triangle[i] as usize].position);
+        let ab = [b[0] - a[0], b[2] - a[2]];
+        let ac = [c[0] - a[0], c[2] - a[2]];
+        // Y component of the cross product of `ab` and `ac` in the x-z plane
+        ab[1] * ac[0] - ab[0] * ac[1]
+    }
+
+    #[test]
+    fn grid_mesh_shares_vertices() {
+        let (vertices, indices) = grid_mesh(10, 0.5, 1, Winding::Clockwise, Diagonal::Uniform);
+
+        assert_eq!(vertices.len(), 11 * 11);
+        assert_eq!(indices.len(), 6 * 10 * 10);
+    }
+
+    #[test]
+    fn coarser_steps_merge_quads() {
+        let (vertices, indices) = grid_mesh(16, 1.0, 4, Winding::Clockwise, Diagonal::Uniform);
+
+        assert_eq!(vertices.len(), 5 * 5);
+        assert_eq!(indices.len(), 6 * 4 * 4);
+    }
+
+    #[test]
+    fn grid_mesh_stays_in_bounds() {
+        let resolution = 12;
+        let quad_size = 0.25;
+        let size = resolution as f32 * quad_size;
+
+        for step in [1, 2, 4] {
+            let (vertices, indices) = grid_mesh(
+                resolution,
+                quad_size,
+                step,
+                Winding::Clockwise,
+                Diagonal::Alternating,
+            );
+
+            assert!(indices
+                .iter()
+                .all(|index| (*index as usize) < vertices.len()));
+            for vertex in &vertices {
+                let [x, y, z] = vertex.position;
+                assert!((0.0..=size).contains(&x) && (0.0..=size).contains(&z));
+                assert_eq!(y, 0.0);
+                assert!(vertex
+                    .tex_coords
+                    .iter()
+                    .all(|coord| (0.0..=resolution as f32).contains(coord)));
+            }
+
+            let max_x = vertices
+                .iter()
+                .map(|vertex| vertex.position[0])
+                .fold(0.0, f32::max);
+            assert_eq!(max_x, size);
+        }
+    }
+
+    #[test]
+    fn winding_sets_triangle_orientation() {
+        for diagonal in [Diagonal::Uniform, Diagonal::Alternating] {
+            let (vertices, clockwise) = grid_mesh(4, 1.0, 1, Winding::Clockwise, diagonal);
+            let (_, counter_clockwise) = grid_mesh(4, 1.0, 1, Winding::CounterClockwise, diagonal);
+
+            assert!(clockwise
+                .chunks(3)
+                .all(|triangle| triangle_normal(&vertices, triangle) < 0.0));
+            assert!(counter_clockwise
+                .chunks(3)
+                .all(|triangle| triangle_normal(&vertices, triangle) > 0.0));
+        }
+    }
+
+    #[test]
+    fn alternating_diagonals_flip_every_other_quad() {
+        let (_, uniform) = grid_mesh(2, 1.0, 1, Winding::Clockwise, Diagonal::Uniform);
+        let (_, alternating) = grid_mesh(2, 1.0, 1, Winding::Clockwise, Diagonal::Alternating);
+
+        // The first quad keeps its diagonal and the second flips it
+        assert_eq!(uniform[..6], alternating[..6]);
+        assert_ne!(uniform[6..12], alternating[6..12]);
+    }
+
+    #[test]
+    fn skirt_hangs_below_every_edge() {
+        let resolution = 8;
+        let step = 2;
+        let mut mesh = grid_mesh(resolution, 1.0, step, Winding::Clockwise, Diagonal::Uniform);
+        let grid_vertex_count = mesh.0.len();
+        let grid_index_count = mesh.1.len();
+
+        add_skirt(&mut mesh, resolution, step, 0.5);
+        let (vertices, indices) = mesh;
+
+        let row_length = resolution / step + 1;
+        assert_eq!(vertices.len(), grid_vertex_count + 4 * row_length);
+        // Two triangles per edge quad, drawn from both sides
+        assert_eq!(indices.len(), grid_index_count + 4 * (row_length - 1) * 12);
+        assert!(indices
+            .iter()
+            .all(|index| (*index as usize) < vertices.len()));
+        assert!(vertices[grid_vertex_count..]
+            .iter()
+            .all(|vertex| vertex.position[1] == -0.5));
+    }
+}
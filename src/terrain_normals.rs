@@ -0,0 +1,204 @@
+use gamezap::texture::Texture;
+use nalgebra::Vector3;
+use rayon::prelude::*;
+
+use crate::heightmap::{Heightmap, HEIGHT_SCALE};
+
+#[derive(Debug, Clone)]
+/// Surface normals and slopes of a heightmap in world space, found with central differences of
+/// the neighbouring cells. Placement passes use the slopes to keep off terrain that is too steep
+pub struct TerrainNormals {
+    normals: Vec<Vector3<f32>>,
+    /// Slope of every cell, as a fraction of a vertical wall
+    slopes: Heightmap,
+}
+
+impl TerrainNormals {
+    pub fn new(heightmap: &Heightmap) -> Self {
+        let resolution = heightmap.resolution();
+        let max_cell = resolution - 1;
+        let cell_size = heightmap.cell_size();
+
+        let normals = (0..resolution * resolution)
+            .into_par_iter()
+            .map(|i| {
+                let x = i % resolution;
+                let y = i / resolution;
+
+                // One sided differences along the border, where a neighbour is missing
+                let (left, right) = (x.saturating_sub(1), (x + 1).min(max_cell));
+                let (up, down) = (y.saturating_sub(1), (y + 1).min(max_cell));
+
+                let gradient_x = (heightmap.get(right, y) - heightmap.get(left, y)) * HEIGHT_SCALE
+                    / ((right - left).max(1) as f32 * cell_size);
+                let gradient_z = (heightmap.get(x, down) - heightmap.get(x, up)) * HEIGHT_SCALE
+                    / ((down - up).max(1) as f32 * cell_size);
+
+                Vector3::new(-gradient_x, 1.0, -gradient_z).normalize()
+            })
+            .collect::<Vec<_>>();
+
+        let mut slopes = Heightmap::new(resolution, heightmap.world_size());
+        slopes
+            .heights_mut()
+            .par_iter_mut()
+            .zip(&normals)
+            .for_each(|(slope, normal)| {
+                *slope = normal.y.clamp(-1.0, 1.0).acos() / std::f32::consts::FRAC_PI_2;
+            });
+
+        Self { normals, slopes }
+    }
+
+    /// The normals without `border` cells on every side, matching [`Heightmap::cropped`]
+    pub fn cropped(&self, border: u32) -> Self {
+        let slopes = self.slopes.cropped(border);
+        let resolution = slopes.resolution();
+        let normals = (0..resolution * resolution)
+            .map(|i| self.normal(i % resolution + border, i / resolution + border))
+            .collect();
+
+        Self { normals, slopes }
+    }
+
+    /// Unit normal of a cell, pointing up
+    pub fn normal(&self, x: u32, y: u32) -> Vector3<f32> {
+        self.normals[(y * self.slopes.resolution() + x) as usize]
+    }
+
+    pub fn normals(&self) -> &[Vector3<f32>] {
+        &self.normals
+    }
+
+    /// Angle between the surface of a cell and the horizontal, in degrees
+    pub fn slope_degrees(&self, x: u32, y: u32) -> f32 {
+        self.slopes.get(x, y) * 90.0
+    }
+
+    /// Whether a cell is too steep to build on
+    pub fn is_steep(&self, x: u32, y: u32, max_slope_degrees: f32) -> bool {
+        self.slope_degrees(x, y) > max_slope_degrees
+    }
+
+    /// Slope of every cell, zero for flat ground and one for a vertical wall
+    pub fn slopes(&self) -> &Heightmap {
+        &self.slopes
+    }
+
+    /// Uploads the normals as an RGBA16F texture, with the normal in the first three channels
+    pub fn create_texture(&self, device: &wgpu::Device, queue: &wgpu::Queue) -> Texture {
+        let resolution = self.slopes.resolution();
+        let texture = device.create_texture(&wgpu::TextureDescriptor {
+            label: Some("Terrain normal map"),
+            size: wgpu::Extent3d {
+                width: resolution,
+                height: resolution,
+                depth_or_array_layers: 1,
+            },
+            mip_level_count: 1,
+            sample_count: 1,
+            dimension: wgpu::TextureDimension::D2,
+            format: wgpu::TextureFormat::Rgba16Float,
+            usage: wgpu::TextureUsages::TEXTURE_BINDING | wgpu::TextureUsages::COPY_DST,
+            view_formats: &[],
+        });
+
+        self.write_texture(queue, &texture);
+
+        let view = texture.create_view(&wgpu::TextureViewDescriptor::default());
+        let sampler = device.create_sampler(&wgpu::SamplerDescriptor {
+            label: Some("Terrain normal map sampler"),
+            address_mode_u: wgpu::AddressMode::ClampToEdge,
+            address_mode_v: wgpu::AddressMode::ClampToEdge,
+            address_mode_w: wgpu::AddressMode::ClampToEdge,
+            mag_filter: wgpu::FilterMode::Linear,
+            min_filter: wgpu::FilterMode::Linear,
+            mipmap_filter: wgpu::FilterMode::Nearest,
+            ..Default::default()
+        });
+
+        Texture {
+            texture,
+            view,
+            sampler,
+        }
+    }
+
+    /// Uploads the normals into an existing texture made by [`TerrainNormals::create_texture`]
+    /// for normals of the same resolution
+    pub fn write_texture(&self, queue: &wgpu::Queue, texture: &wgpu::Texture) {
+        let resolution = self.slopes.resolution();
+        let half_normals = self
+            .normals
+            .iter()
+            .flat_map(|normal| [normal.x, normal.y, normal.z, 0.0].map(half::f16::from_f32))
+            .collect::<Vec<_>>();
+
+        queue.write_texture(
+            wgpu::ImageCopyTexture {
+                texture,
+                mip_level: 0,
+                origin: wgpu::Origin3d::ZERO,
+                aspect: wgpu::TextureAspect::All,
+            },
+            bytemuck::cast_slice(&half_normals),
+            wgpu::ImageDataLayout {
+                offset: 0,
+                bytes_per_row: Some(4 * std::mem::size_of::<half::f16>() as u32 * resolution),
+                rows_per_image: Some(resolution),
+            },
+            wgpu::Extent3d {
+                width: resolution,
+                height: resolution,
+                depth_or_array_layers: 1,
+            },
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn flat_ground_points_straight_up() {
+        let normals = TerrainNormals::new(&Heightmap::from_fn(10, 8.0, |_, _| 0.4));
+
+        for y in 0..10 {
+            for x in 0..10 {
+                assert_eq!(normals.normal(x, y), Vector3::new(0.0, 1.0, 0.0));
+                assert_eq!(normals.slope_degrees(x, y), 0.0);
+            }
+        }
+    }
+
+    #[test]
+    fn incline_has_the_expected_slope() {
+        // Every cell is one world unit wide and one world unit higher than the last, so the ground
+        // rises at 45 degrees along x
+        let heightmap = Heightmap::from_fn(10, 8.0, |x, _| x as f32 / HEIGHT_SCALE);
+        let normals = TerrainNormals::new(&heightmap);
+
+        let expected = Vector3::new(-1.0, 1.0, 0.0).normalize();
+        for y in 0..10 {
+            for x in 0..10 {
+                assert!((normals.normal(x, y) - expected).norm() < 1e-5);
+                assert!((normals.slope_degrees(x, y) - 45.0).abs() < 1e-3);
+            }
+        }
+        assert!(normals.is_steep(4, 4, 30.0));
+        assert!(!normals.is_steep(4, 4, 60.0));
+    }
+
+    #[test]
+    fn cropping_keeps_the_inner_cells() {
+        let heightmap = Heightmap::from_fn(12, 10.0, |x, y| (x * y) as f32 * 0.01);
+        let normals = TerrainNormals::new(&heightmap);
+        let cropped = normals.cropped(1);
+
+        assert_eq!(cropped.slopes().resolution(), 10);
+        assert_eq!(cropped.slopes().cell_size(), heightmap.cell_size());
+        assert_eq!(cropped.normal(0, 0), normals.normal(1, 1));
+        assert_eq!(cropped.normal(9, 4), normals.normal(10, 5));
+    }
+}
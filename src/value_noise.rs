@@ -0,0 +1,74 @@
+use crate::{
+    noise::{hash_to_unit, lattice_hash, Noise2D},
+    perlin_noise::PerlinNoise,
+};
+
+#[derive(Debug, Clone)]
+/// Random values at every lattice point, smoothly interpolated in between. Blockier than gradient
+/// noise, but the cheapest of the generators
+pub struct ValueNoise {
+    seed: u64,
+}
+
+impl ValueNoise {
+    pub fn new(seed: u64) -> Self {
+        Self { seed }
+    }
+
+    fn value(&self, x: i32, y: i32) -> f32 {
+        hash_to_unit(lattice_hash(self.seed, x, y)) * 2.0 - 1.0
+    }
+
+    fn fade(t: f32) -> f32 {
+        t * t * t * (t * (t * 6.0 - 15.0) + 10.0)
+    }
+}
+
+impl Noise2D for ValueNoise {
+    fn sample(&self, x: f32, y: f32) -> f32 {
+        let x_floor = x.floor();
+        let y_floor = y.floor();
+        let u = Self::fade(x - x_floor);
+        let v = Self::fade(y - y_floor);
+        let x_floor = x_floor as i32;
+        let y_floor = y_floor as i32;
+
+        let top_lerp = PerlinNoise::lerp(
+            self.value(x_floor, y_floor),
+            self.value(x_floor + 1, y_floor),
+            u,
+        );
+        let bottom_lerp = PerlinNoise::lerp(
+            self.value(x_floor, y_floor + 1),
+            self.value(x_floor + 1, y_floor + 1),
+            u,
+        );
+
+        PerlinNoise::lerp(top_lerp, bottom_lerp, v)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn samples() -> impl Iterator<Item = (f32, f32)> {
+        (0..1600).map(|i| ((i % 40) as f32 * 0.37 - 7.0, (i / 40) as f32 * 0.53 - 9.0))
+    }
+
+    #[test]
+    fn same_seed_gives_the_same_noise() {
+        let (first, second, other) = (ValueNoise::new(9), ValueNoise::new(9), ValueNoise::new(10));
+        assert!(samples().all(|(x, y)| first.sample(x, y) == second.sample(x, y)));
+        assert!(samples().any(|(x, y)| first.sample(x, y) != other.sample(x, y)));
+    }
+
+    #[test]
+    fn noise_stays_in_range_and_hits_the_lattice_values() {
+        let noise = ValueNoise::new(4);
+        assert!(samples().all(|(x, y)| (-1.0..=1.0).contains(&noise.sample(x, y))));
+        for (x, y) in [(0, 0), (3, -2), (-5, 7)] {
+            assert_eq!(noise.sample(x as f32, y as f32), noise.value(x, y));
+        }
+    }
+}
@@ -0,0 +1,1322 @@
+use std::{fmt::Display, path::Path};
+
+use serde::{Deserialize, Serialize};
+
+use crate::{biome::Biome, perlin_noise::PerlinNoise};
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+/// Every tweakable parameter of world generation. Loaded from a TOML file, where any omitted
+/// field falls back to its default.
+pub struct WorldConfig {
+    pub seed: u64,
+    pub terrain: TerrainConfig,
+    pub chunks: ChunkConfig,
+    pub hydraulic_erosion: HydraulicErosionConfig,
+    pub thermal_erosion: ThermalErosionConfig,
+    pub ocean: OceanConfig,
+    pub lakes: LakeConfig,
+    pub flow: FlowConfig,
+    pub river: RiverConfig,
+    pub biomes: BiomeConfig,
+    pub resources: ResourcesConfig,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct TerrainConfig {
+    /// Number of quads along each side of the terrain mesh
+    pub resolution: usize,
+    /// Side length of the terrain in world units
+    pub size: f32,
+    pub noise: NoiseKind,
+    /// Number of noise grid cells along each side of the terrain. The `perlin_` settings apply to
+    /// every noise generator
+    pub perlin_size: usize,
+    pub perlin_octaves: usize,
+    pub perlin_persistence: f32,
+    /// Makes Perlin noise repeat every this many noise units, a multiple of
+    /// `2^(perlin_octaves - 1)`. The mesh spans `resolution / cells` noise units, where `cells` is
+    /// `(resolution + 1) / perlin_size` rounded down, and the terrain tiles seamlessly when that
+    /// span is a whole multiple of the period. For example `resolution = 320` and
+    /// `perlin_size = 32` span 32 units, which tile with a period of 16 or 32 at 5 octaves. Zero
+    /// disables tiling
+    pub perlin_tile_period: u32,
+    /// Composes the terrain from several noise nodes. When set, `noise` is ignored
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub graph: Option<NoiseNode>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+/// Noise generator the terrain heights are sampled from
+pub enum NoiseKind {
+    Perlin,
+    Simplex,
+    OpenSimplex2,
+    /// Distance to the nearest cellular feature point
+    WorleyF1,
+    /// Distance to the second nearest cellular feature point
+    WorleyF2,
+    /// Difference between the two, which is zero along the cell borders
+    WorleyF2MinusF1,
+    Value,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case", deny_unknown_fields)]
+/// A node of the terrain noise graph. Every node is sampled in the same noise units as the
+/// terrain noise, and outputs values roughly in `[-1, 1]` unless it rescales them
+pub enum NoiseNode {
+    /// Fractal noise from one of the generators
+    Generator {
+        noise: NoiseKind,
+        /// Multiplies the coordinates, so values above one give smaller features
+        #[serde(default = "default_frequency")]
+        frequency: f32,
+        #[serde(default = "default_octaves")]
+        octaves: usize,
+        #[serde(default = "default_persistence")]
+        persistence: f32,
+        /// Added to the world seed, so that generators of the same kind differ
+        #[serde(default)]
+        seed_offset: u64,
+    },
+    /// Sharp ridges along the zero crossings of `source`, for mountain ranges
+    Ridged {
+        source: Box<NoiseNode>,
+        #[serde(default = "default_octaves")]
+        octaves: usize,
+        #[serde(default = "default_ridge_gain")]
+        gain: f32,
+    },
+    /// Rounded lumps from the absolute value of `source`, for hills and plains
+    Billow {
+        source: Box<NoiseNode>,
+        #[serde(default = "default_octaves")]
+        octaves: usize,
+        #[serde(default = "default_persistence")]
+        persistence: f32,
+    },
+    Turbulence {
+        source: Box<NoiseNode>,
+        #[serde(default = "default_octaves")]
+        octaves: usize,
+        #[serde(default = "default_persistence")]
+        persistence: f32,
+    },
+    /// Offsets the coordinates of `source` by `warp` times `strength`, in noise units
+    DomainWarp {
+        source: Box<NoiseNode>,
+        warp: Box<NoiseNode>,
+        strength: f32,
+    },
+    Add {
+        a: Box<NoiseNode>,
+        b: Box<NoiseNode>,
+    },
+    Multiply {
+        a: Box<NoiseNode>,
+        b: Box<NoiseNode>,
+    },
+    /// `low` where `control` is below `threshold` and `high` above it, blended within `falloff`
+    Select {
+        control: Box<NoiseNode>,
+        low: Box<NoiseNode>,
+        high: Box<NoiseNode>,
+        #[serde(default)]
+        threshold: f32,
+        #[serde(default)]
+        falloff: f32,
+    },
+    Clamp {
+        source: Box<NoiseNode>,
+        min: f32,
+        max: f32,
+    },
+    /// Remaps `source` through `[input, output]` control points sorted by input
+    Curve {
+        source: Box<NoiseNode>,
+        points: Vec<[f32; 2]>,
+    },
+    ScaleBias {
+        source: Box<NoiseNode>,
+        #[serde(default = "default_scale")]
+        scale: f32,
+        #[serde(default)]
+        bias: f32,
+    },
+    Constant {
+        value: f32,
+    },
+}
+
+fn default_frequency() -> f32 {
+    1.0
+}
+
+fn default_scale() -> f32 {
+    1.0
+}
+
+fn default_octaves() -> usize {
+    1
+}
+
+fn default_persistence() -> f32 {
+    0.5
+}
+
+fn default_ridge_gain() -> f32 {
+    2.0
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+/// Splits the world into square chunks that are generated around the camera as it moves. Chunks
+/// sample the terrain noise at their world position, so the terrain continues past the edge of
+/// the map
+pub struct ChunkConfig {
+    /// Side length of a chunk in world units
+    pub size: f32,
+    /// Number of quads along each side of a chunk mesh
+    pub resolution: usize,
+    /// Chunks up to this many chunks away from the camera are drawn. Zero disables streaming and
+    /// draws the single terrain mesh
+    pub load_radius: usize,
+    /// Chunks further than this many chunks away are dropped. Chunks in between stay cached so
+    /// that turning back does not regenerate them
+    pub unload_radius: usize,
+    /// Number of coarser levels of detail. Every level halves the quads along each side of a
+    /// chunk, so `resolution` has to be a multiple of `2^lod_levels`. Zero draws every chunk at
+    /// full resolution
+    pub lod_levels: u32,
+    /// Number of rings of chunks around the camera drawn at each level of detail
+    pub lod_ring_width: usize,
+    /// Depth of the skirt hung below the edges of every chunk to hide cracks between levels of
+    /// detail, in world units. Zero disables skirts
+    pub skirt_depth: f32,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+/// Droplet simulation parameters. Heights are in the heightmap's normalized units, and distances
+/// in heightmap cells.
+pub struct HydraulicErosionConfig {
+    /// Number of droplets to simulate. Zero disables the pass
+    pub droplet_count: usize,
+    /// Maximum number of steps a droplet takes before it evaporates
+    pub max_lifetime: usize,
+    /// How much a droplet keeps its previous direction instead of following the slope, in `[0, 1]`
+    pub inertia: f32,
+    /// Multiplier for how much sediment a droplet can carry
+    pub sediment_capacity: f32,
+    /// Capacity floor, so droplets on flat ground still carry a little sediment
+    pub min_sediment_capacity: f32,
+    /// Fraction of the excess sediment dropped per step, in `[0, 1]`
+    pub deposition: f32,
+    /// Fraction of the free capacity filled by eroding per step, in `[0, 1]`
+    pub erosion: f32,
+    /// Fraction of the water lost per step, in `[0, 1]`
+    pub evaporation: f32,
+    pub gravity: f32,
+    /// Radius of the area a droplet erodes from, in cells
+    pub erosion_radius: usize,
+    pub initial_water: f32,
+    pub initial_speed: f32,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ThermalErosionConfig {
+    /// Number of relaxation passes. Zero disables the pass
+    pub iterations: usize,
+    /// Steepest stable slope, in degrees
+    pub talus_angle: f32,
+    /// Fraction of the excess material moved per pass, in `[0, 1]`
+    pub rate: f32,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum OceanFalloff {
+    /// Leaves the terrain untouched
+    None,
+    /// Sinks the terrain towards every edge, making an island
+    Radial,
+    /// Sinks the terrain towards one map edge, making a coast
+    Edge,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct OceanConfig {
+    /// Cells below this height that connect to the map edge are ocean, in normalized height
+    /// units. Zero disables the ocean
+    pub sea_level: f32,
+    pub falloff: OceanFalloff,
+    /// Fraction of the distance to the coast at which the terrain starts sinking, in `[0, 1)`
+    pub falloff_start: f32,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct LakeConfig {
+    /// Number of lakes kept, largest first. Zero disables lakes
+    pub max_count: usize,
+    /// Smallest lake kept, in cells
+    pub min_area: usize,
+    /// Lakes shallower than this at their deepest point are dropped, in normalized height units
+    pub min_depth: f32,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum FlowMethod {
+    /// All water flows to the steepest of the eight neighbours
+    D8,
+    /// Water is split between the two neighbours around the steepest slope direction
+    DInfinity,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct FlowConfig {
+    pub method: FlowMethod,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum RiverMode {
+    /// Runs from a random land cell to a random point on the coast, ignoring the terrain. Without
+    /// an ocean it connects two random map edges
+    Random,
+    /// Flows from a high point down along the terrain to the coast, or the map edge without an
+    /// ocean
+    Downhill,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+/// How the river mask fades from the center of the river to its edge
+pub enum RiverFalloff {
+    Linear,
+    Quadratic,
+    Smoothstep,
+    Gaussian,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct RiverConfig {
+    pub mode: RiverMode,
+    pub count: usize,
+    /// Radius around the river curve at its mouth, in world units
+    pub size: f32,
+    /// Radius at the source, as a fraction of the radius at the mouth
+    pub source_width_ratio: f32,
+    /// How much the radius wobbles along the river, as a fraction of the radius
+    pub width_noise: f32,
+    pub falloff: RiverFalloff,
+    /// Number of chained bezier segments in random rivers
+    pub segment_count: usize,
+    /// Only used by random rivers
+    pub shift_iterations: usize,
+    /// How far below the water level the river bed is carved, in normalized height units. Zero
+    /// leaves the terrain untouched
+    pub carve_depth: f32,
+    /// Width of the smoothed bank around the river, in world units
+    pub bank_width: f32,
+    /// Number of tributaries joining each main river
+    pub tributary_count: usize,
+    /// Radius around each tributary curve, in world units
+    pub tributary_size: f32,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+/// Climate used to classify every cell into a biome. Moisture and temperature are both in
+/// `[0, 1]`, and heights are in normalized height units
+pub struct BiomeConfig {
+    /// Distance from water at which moisture has fallen to about a third, in world units
+    pub moisture_range: f32,
+    /// Largest random change in moisture
+    pub moisture_noise: f32,
+    /// Temperature at sea level along the top edge of the map
+    pub north_temperature: f32,
+    /// Temperature at sea level along the bottom edge of the map
+    pub south_temperature: f32,
+    /// Temperature lost between sea level and the highest point of the map
+    pub lapse_rate: f32,
+    /// Largest random change in temperature
+    pub temperature_noise: f32,
+    /// Land up to this far above sea level is beach
+    pub beach_height: f32,
+    /// Land above this height is mountain
+    pub mountain_height: f32,
+    /// Slopes steeper than this are mountain, in degrees
+    pub mountain_slope: f32,
+    /// Colder than this is tundra
+    pub tundra_temperature: f32,
+    /// Warmer than this and drier than `desert_moisture` is desert
+    pub desert_temperature: f32,
+    pub desert_moisture: f32,
+    /// Wetter than this is forest
+    pub forest_moisture: f32,
+    /// Wetter than this is wetland
+    pub wetland_moisture: f32,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+/// A natural resource found in deposits across the map
+pub enum ResourceKind {
+    Ore,
+    Oil,
+    FertileSoil,
+    Timber,
+    Stone,
+    FreshWater,
+}
+
+impl ResourceKind {
+    pub const COUNT: usize = 6;
+    /// Every kind, in the order their layers are stored
+    pub const ALL: [Self; Self::COUNT] = [
+        Self::Ore,
+        Self::Oil,
+        Self::FertileSoil,
+        Self::Timber,
+        Self::Stone,
+        Self::FreshWater,
+    ];
+
+    /// Name of the kind as written in the config
+    pub fn name(self) -> &'static str {
+        match self {
+            Self::Ore => "ore",
+            Self::Oil => "oil",
+            Self::FertileSoil => "fertile_soil",
+            Self::Timber => "timber",
+            Self::Stone => "stone",
+            Self::FreshWater => "fresh_water",
+        }
+    }
+
+    /// Position of the kind in [`ResourceKind::ALL`]
+    pub fn index(self) -> usize {
+        self as usize
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+/// Deposits of every kind of resource, each in its own table
+pub struct ResourcesConfig {
+    /// Tints the terrain with the resources of every cell, for checking where deposits lie
+    pub overlay: bool,
+    pub ore: ResourceConfig,
+    pub oil: ResourceConfig,
+    pub fertile_soil: ResourceConfig,
+    pub timber: ResourceConfig,
+    pub stone: ResourceConfig,
+    pub fresh_water: ResourceConfig,
+}
+
+impl ResourcesConfig {
+    pub fn get(&self, kind: ResourceKind) -> &ResourceConfig {
+        match kind {
+            ResourceKind::Ore => &self.ore,
+            ResourceKind::Oil => &self.oil,
+            ResourceKind::FertileSoil => &self.fertile_soil,
+            ResourceKind::Timber => &self.timber,
+            ResourceKind::Stone => &self.stone,
+            ResourceKind::FreshWater => &self.fresh_water,
+        }
+    }
+
+    pub fn get_mut(&mut self, kind: ResourceKind) -> &mut ResourceConfig {
+        match kind {
+            ResourceKind::Ore => &mut self.ore,
+            ResourceKind::Oil => &mut self.oil,
+            ResourceKind::FertileSoil => &mut self.fertile_soil,
+            ResourceKind::Timber => &mut self.timber,
+            ResourceKind::Stone => &mut self.stone,
+            ResourceKind::FreshWater => &mut self.fresh_water,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+/// Splat parameters of one kind of resource, all measured in texture pixels, and where its
+/// deposits may lie. Heights are in normalized height units
+pub struct ResourceConfig {
+    pub splat_count: usize,
+    pub splat_spread: f32,
+    /// Smallest distance between the origins of two splats. Generation fails when
+    /// `splat_count` splats this far apart do not fit where the resource may lie
+    pub spread_between_splats: f32,
+    pub points_per_splat: usize,
+    pub magnitude: f32,
+    pub spread: f32,
+    /// Chance that each of the `splat_count` deposits is left out, so rarer resources turn up in
+    /// fewer places
+    pub rarity: f32,
+    /// Biomes the resource may lie in
+    pub biomes: Vec<Biome>,
+    pub min_height: f32,
+    pub max_height: f32,
+    /// Drier cells than this hold none of the resource, so a high value keeps it near water
+    pub min_moisture: f32,
+    /// Units of the resource in a cell at the richest point of a deposit
+    pub quantity: f32,
+    /// Fraction of its starting quantity a cell grows back every second. Zero for resources that
+    /// only deplete
+    pub regeneration: f32,
+}
+
+impl Default for TerrainConfig {
+    fn default() -> Self {
+        Self {
+            resolution: 300,
+            size: 20.0,
+            noise: NoiseKind::Perlin,
+            perlin_size: 30,
+            perlin_octaves: 5,
+            perlin_persistence: 0.5,
+            perlin_tile_period: 0,
+            graph: None,
+        }
+    }
+}
+
+impl Default for ChunkConfig {
+    fn default() -> Self {
+        Self {
+            size: 20.0,
+            resolution: 100,
+            load_radius: 0,
+            unload_radius: 2,
+            lod_levels: 0,
+            lod_ring_width: 1,
+            skirt_depth: 0.5,
+        }
+    }
+}
+
+impl Default for HydraulicErosionConfig {
+    fn default() -> Self {
+        Self {
+            droplet_count: 0,
+            max_lifetime: 30,
+            inertia: 0.05,
+            sediment_capacity: 4.0,
+            min_sediment_capacity: 0.01,
+            deposition: 0.3,
+            erosion: 0.3,
+            evaporation: 0.01,
+            gravity: 4.0,
+            erosion_radius: 3,
+            initial_water: 1.0,
+            initial_speed: 1.0,
+        }
+    }
+}
+
+impl Default for ThermalErosionConfig {
+    fn default() -> Self {
+        Self {
+            iterations: 0,
+            talus_angle: 35.0,
+            rate: 0.5,
+        }
+    }
+}
+
+impl Default for OceanConfig {
+    fn default() -> Self {
+        Self {
+            sea_level: 0.0,
+            falloff: OceanFalloff::None,
+            falloff_start: 0.5,
+        }
+    }
+}
+
+impl Default for LakeConfig {
+    fn default() -> Self {
+        Self {
+            max_count: 0,
+            min_area: 20,
+            min_depth: 0.005,
+        }
+    }
+}
+
+impl Default for FlowConfig {
+    fn default() -> Self {
+        Self {
+            method: FlowMethod::D8,
+        }
+    }
+}
+
+impl Default for RiverConfig {
+    fn default() -> Self {
+        Self {
+            mode: RiverMode::Random,
+            count: 1,
+            size: 1.0,
+            source_width_ratio: 1.0,
+            width_noise: 0.0,
+            falloff: RiverFalloff::Quadratic,
+            segment_count: 1,
+            shift_iterations: 20,
+            carve_depth: 0.0,
+            bank_width: 1.0,
+            tributary_count: 0,
+            tributary_size: 0.6,
+        }
+    }
+}
+
+impl Default for BiomeConfig {
+    fn default() -> Self {
+        Self {
+            moisture_range: 3.0,
+            moisture_noise: 0.15,
+            north_temperature: 0.3,
+            south_temperature: 0.9,
+            lapse_rate: 0.3,
+            temperature_noise: 0.1,
+            beach_height: 0.02,
+            mountain_height: 0.8,
+            mountain_slope: 35.0,
+            tundra_temperature: 0.3,
+            desert_temperature: 0.45,
+            desert_moisture: 0.35,
+            forest_moisture: 0.45,
+            wetland_moisture: 0.85,
+        }
+    }
+}
+
+impl Default for ResourcesConfig {
+    fn default() -> Self {
+        Self {
+            overlay: false,
+            ore: ResourceConfig {
+                splat_count: 4,
+                splat_spread: 12.0,
+                spread_between_splats: 40.0,
+                magnitude: 12.0,
+                spread: 8.0,
+                rarity: 0.25,
+                min_height: 0.55,
+                ..Default::default()
+            },
+            oil: ResourceConfig {
+                splat_count: 3,
+                spread_between_splats: 80.0,
+                magnitude: 20.0,
+                spread: 10.0,
+                rarity: 0.5,
+                biomes: vec![Biome::Desert, Biome::Wetland, Biome::Grassland],
+                ..Default::default()
+            },
+            fertile_soil: ResourceConfig {
+                splat_count: 6,
+                spread_between_splats: 50.0,
+                biomes: vec![Biome::Grassland, Biome::Forest, Biome::Wetland],
+                min_moisture: 0.6,
+                regeneration: 0.001,
+                ..Default::default()
+            },
+            timber: ResourceConfig {
+                splat_count: 8,
+                spread_between_splats: 40.0,
+                biomes: vec![Biome::Forest],
+                regeneration: 0.002,
+                ..Default::default()
+            },
+            stone: ResourceConfig {
+                splat_count: 5,
+                splat_spread: 15.0,
+                spread_between_splats: 50.0,
+                magnitude: 15.0,
+                spread: 10.0,
+                min_height: 0.45,
+                ..Default::default()
+            },
+            fresh_water: ResourceConfig {
+                splat_count: 6,
+                spread_between_splats: 40.0,
+                biomes: vec![
+                    Biome::Grassland,
+                    Biome::Forest,
+                    Biome::Tundra,
+                    Biome::Wetland,
+                ],
+                min_moisture: 0.7,
+                regeneration: 0.01,
+                ..Default::default()
+            },
+        }
+    }
+}
+
+impl Default for ResourceConfig {
+    fn default() -> Self {
+        Self {
+            splat_count: 5,
+            splat_spread: 30.0,
+            spread_between_splats: 100.0,
+            points_per_splat: 3,
+            magnitude: 30.0,
+            spread: 15.0,
+            rarity: 0.0,
+            biomes: Biome::LAND.to_vec(),
+            min_height: 0.0,
+            max_height: 1.0,
+            min_moisture: 0.0,
+            quantity: 100.0,
+            regeneration: 0.0,
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum WorldConfigError {
+    Io {
+        path: String,
+        source: std::io::Error,
+    },
+    Parse {
+        path: String,
+        source: toml::de::Error,
+    },
+    OutOfRange {
+        /// Path of the field in the TOML file, like `terrain.resolution`
+        field: String,
+        value: String,
+        expected: String,
+    },
+}
+
+impl Display for WorldConfigError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Io { path, source } => write!(f, "could not read world config {path}: {source}"),
+            Self::Parse { path, source } => write!(f, "invalid world config {path}: {source}"),
+            Self::OutOfRange {
+                field,
+                value,
+                expected,
+            } => write!(
+                f,
+                "world config field `{field}` is {value}, expected {expected}"
+            ),
+        }
+    }
+}
+
+impl std::error::Error for WorldConfigError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Self::Io { source, .. } => Some(source),
+            Self::Parse { source, .. } => Some(source),
+            Self::OutOfRange { .. } => None,
+        }
+    }
+}
+
+impl WorldConfig {
+    /// Reads and validates a world config from a TOML file
+    pub fn load(path: impl AsRef<Path>) -> Result<Self, WorldConfigError> {
+        let path = path.as_ref();
+        let contents = std::fs::read_to_string(path).map_err(|source| WorldConfigError::Io {
+            path: path.display().to_string(),
+            source,
+        })?;
+
+        let config: Self = toml::from_str(&contents).map_err(|source| WorldConfigError::Parse {
+            path: path.display().to_string(),
+            source,
+        })?;
+        config.validate()?;
+
+        Ok(config)
+    }
+
+    /// Same as [`WorldConfig::load`], but falls back to the default world if the file does not
+    /// exist
+    pub fn load_or_default(path: impl AsRef<Path>) -> Result<Self, WorldConfigError> {
+        if path.as_ref().exists() {
+            Self::load(path)
+        } else {
+            Ok(Self::default())
+        }
+    }
+
+    pub fn validate(&self) -> Result<(), WorldConfigError> {
+        let terrain = &self.terrain;
+        check(
+            "terrain.resolution",
+            terrain.resolution,
+            terrain.resolution > 0,
+            "at least 1",
+        )?;
+        check(
+            "terrain.size",
+            terrain.size,
+            terrain.size.is_finite() && terrain.size > 0.0,
+            "a positive number",
+        )?;
+        check(
+            "terrain.perlin_size",
+            terrain.perlin_size,
+            terrain.perlin_size > 0 && terrain.perlin_size <= terrain.resolution + 1,
+            format!(
+                "between 1 and terrain.resolution + 1 ({})",
+                terrain.resolution + 1
+            ),
+        )?;
+        check(
+            "terrain.perlin_octaves",
+            terrain.perlin_octaves,
+            terrain.perlin_octaves > 0,
+            "at least 1",
+        )?;
+        check(
+            "terrain.perlin_persistence",
+            terrain.perlin_persistence,
+            terrain.perlin_persistence > 0.0 && terrain.perlin_persistence <= 1.0,
+            "a number in (0, 1]",
+        )?;
+        let coarsest_scale = PerlinNoise::coarsest_scale(terrain.perlin_octaves);
+        check(
+            "terrain.perlin_tile_period",
+            terrain.perlin_tile_period,
+            terrain.perlin_tile_period.is_multiple_of(coarsest_scale),
+            format!("zero or a multiple of 2^(terrain.perlin_octaves - 1) ({coarsest_scale})"),
+        )?;
+        if let Some(graph) = &terrain.graph {
+            graph.validate("terrain.graph")?;
+        }
+
+        let chunks = &self.chunks;
+        check(
+            "chunks.size",
+            chunks.size,
+            chunks.size.is_finite() && chunks.size > 0.0,
+            "a positive number",
+        )?;
+        check(
+            "chunks.resolution",
+            chunks.resolution,
+            chunks.resolution > 0,
+            "at least 1",
+        )?;
+        check(
+            "chunks.unload_radius",
+            chunks.unload_radius,
+            chunks.unload_radius >= chunks.load_radius,
+            format!("at least chunks.load_radius ({})", chunks.load_radius),
+        )?;
+        check(
+            "chunks.lod_levels",
+            chunks.lod_levels,
+            chunks.lod_levels < usize::BITS
+                && chunks.resolution.is_multiple_of(1 << chunks.lod_levels),
+            format!(
+                "a value where 2^chunks.lod_levels divides chunks.resolution ({})",
+                chunks.resolution
+            ),
+        )?;
+        check(
+            "chunks.lod_ring_width",
+            chunks.lod_ring_width,
+            chunks.lod_ring_width > 0,
+            "at least 1",
+        )?;
+        check(
+            "chunks.skirt_depth",
+            chunks.skirt_depth,
+            chunks.skirt_depth >= 0.0,
+            "zero or more",
+        )?;
+
+        let erosion = &self.hydraulic_erosion;
+        for (field, value) in [
+            ("hydraulic_erosion.inertia", erosion.inertia),
+            ("hydraulic_erosion.deposition", erosion.deposition),
+            ("hydraulic_erosion.erosion", erosion.erosion),
+            ("hydraulic_erosion.evaporation", erosion.evaporation),
+        ] {
+            check(
+                field,
+                value,
+                (0.0..=1.0).contains(&value),
+                "a number in [0, 1]",
+            )?;
+        }
+        for (field, value) in [
+            (
+                "hydraulic_erosion.sediment_capacity",
+                erosion.sediment_capacity,
+            ),
+            (
+                "hydraulic_erosion.min_sediment_capacity",
+                erosion.min_sediment_capacity,
+            ),
+            ("hydraulic_erosion.gravity", erosion.gravity),
+            ("hydraulic_erosion.initial_water", erosion.initial_water),
+            ("hydraulic_erosion.initial_speed", erosion.initial_speed),
+        ] {
+            check(
+                field,
+                value,
+                value.is_finite() && value >= 0.0,
+                "zero or more",
+            )?;
+        }
+        check(
+            "hydraulic_erosion.erosion_radius",
+            erosion.erosion_radius,
+            erosion.erosion_radius > 0,
+            "at least 1",
+        )?;
+
+        let thermal = &self.thermal_erosion;
+        check(
+            "thermal_erosion.talus_angle",
+            thermal.talus_angle,
+            thermal.talus_angle >= 0.0 && thermal.talus_angle < 90.0,
+            "an angle in [0, 90) degrees",
+        )?;
+        check(
+            "thermal_erosion.rate",
+            thermal.rate,
+            (0.0..=1.0).contains(&thermal.rate),
+            "a number in [0, 1]",
+        )?;
+
+        check(
+            "ocean.sea_level",
+            self.ocean.sea_level,
+            (0.0..=1.0).contains(&self.ocean.sea_level),
+            "a number in [0, 1]",
+        )?;
+        check(
+            "ocean.falloff_start",
+            self.ocean.falloff_start,
+            (0.0..1.0).contains(&self.ocean.falloff_start),
+            "a number in [0, 1)",
+        )?;
+
+        check(
+            "lakes.min_depth",
+            self.lakes.min_depth,
+            self.lakes.min_depth >= 0.0,
+            "zero or more",
+        )?;
+
+        check(
+            "river.size",
+            self.river.size,
+            self.river.size.is_finite() && self.river.size > 0.0,
+            "a positive number",
+        )?;
+        check(
+            "river.source_width_ratio",
+            self.river.source_width_ratio,
+            self.river.source_width_ratio.is_finite() && self.river.source_width_ratio > 0.0,
+            "a positive number",
+        )?;
+        check(
+            "river.width_noise",
+            self.river.width_noise,
+            (0.0..1.0).contains(&self.river.width_noise),
+            "a number in [0, 1)",
+        )?;
+        check(
+            "river.segment_count",
+            self.river.segment_count,
+            self.river.segment_count > 0,
+            "at least 1",
+        )?;
+        check(
+            "river.carve_depth",
+            self.river.carve_depth,
+            self.river.carve_depth >= 0.0,
+            "zero or more",
+        )?;
+        check(
+            "river.bank_width",
+            self.river.bank_width,
+            self.river.bank_width >= 0.0,
+            "zero or more",
+        )?;
+        check(
+            "river.tributary_size",
+            self.river.tributary_size,
+            self.river.tributary_size.is_finite() && self.river.tributary_size > 0.0,
+            "a positive number",
+        )?;
+
+        let biomes = &self.biomes;
+        check(
+            "biomes.moisture_range",
+            biomes.moisture_range,
+            biomes.moisture_range.is_finite() && biomes.moisture_range > 0.0,
+            "a positive number",
+        )?;
+        for (field, value) in [
+            ("biomes.moisture_noise", biomes.moisture_noise),
+            ("biomes.north_temperature", biomes.north_temperature),
+            ("biomes.south_temperature", biomes.south_temperature),
+            ("biomes.lapse_rate", biomes.lapse_rate),
+            ("biomes.temperature_noise", biomes.temperature_noise),
+            ("biomes.beach_height", biomes.beach_height),
+            ("biomes.mountain_height", biomes.mountain_height),
+            ("biomes.tundra_temperature", biomes.tundra_temperature),
+            ("biomes.desert_temperature", biomes.desert_temperature),
+            ("biomes.desert_moisture", biomes.desert_moisture),
+            ("biomes.forest_moisture", biomes.forest_moisture),
+            ("biomes.wetland_moisture", biomes.wetland_moisture),
+        ] {
+            check(
+                field,
+                value,
+                (0.0..=1.0).contains(&value),
+                "a number in [0, 1]",
+            )?;
+        }
+        check(
+            "biomes.mountain_slope",
+            biomes.mountain_slope,
+            (0.0..=90.0).contains(&biomes.mountain_slope),
+            "an angle in [0, 90]",
+        )?;
+
+        for kind in ResourceKind::ALL {
+            let resources = self.resources.get(kind);
+            let field = |field: &str| format!("resources.{}.{field}", kind.name());
+            check(
+                field("splat_count"),
+                resources.splat_count,
+                resources.splat_count > 0,
+                "at least 1",
+            )?;
+            check(
+                field("splat_spread"),
+                resources.splat_spread,
+                resources.splat_spread > 0.0,
+                "a positive number",
+            )?;
+            check(
+                field("spread_between_splats"),
+                resources.spread_between_splats,
+                resources.spread_between_splats > 0.0,
+                "a positive number",
+            )?;
+            check(
+                field("magnitude"),
+                resources.magnitude,
+                resources.magnitude > 0.0,
+                "a positive number",
+            )?;
+            check(
+                field("spread"),
+                resources.spread,
+                resources.spread > 0.0,
+                "a positive number",
+            )?;
+            check(
+                field("rarity"),
+                resources.rarity,
+                (0.0..1.0).contains(&resources.rarity),
+                "a number in [0, 1)",
+            )?;
+            check(
+                field("biomes"),
+                resources.biomes.len(),
+                !resources.biomes.is_empty(),
+                "at least one biome",
+            )?;
+            check(
+                field("max_height"),
+                resources.max_height,
+                resources.max_height >= resources.min_height,
+                "at least min_height",
+            )?;
+            check(
+                field("min_moisture"),
+                resources.min_moisture,
+                (0.0..=1.0).contains(&resources.min_moisture),
+                "a number in [0, 1]",
+            )?;
+            check(
+                field("quantity"),
+                resources.quantity,
+                resources.quantity > 0.0,
+                "a positive number",
+            )?;
+            check(
+                field("regeneration"),
+                resources.regeneration,
+                resources.regeneration >= 0.0,
+                "zero or more",
+            )?;
+        }
+
+        Ok(())
+    }
+
+    /// Side length of the generated map textures, which have a one pixel border around the
+    /// terrain
+    pub fn texture_resolution(&self) -> u32 {
+        self.terrain.resolution as u32 + 2
+    }
+}
+
+impl NoiseNode {
+    /// Checks this node and every node below it. `path` is where this node sits in the TOML
+    /// file, like `terrain.graph.high.source`, so errors name the failing node
+    pub fn validate(&self, path: &str) -> Result<(), WorldConfigError> {
+        let child = |name: &str| format!("{path}.{name}");
+        match self {
+            Self::Generator {
+                frequency,
+                octaves,
+                persistence,
+                ..
+            } => {
+                check(
+                    child("frequency"),
+                    frequency,
+                    frequency.is_finite() && *frequency > 0.0,
+                    "a positive number",
+                )?;
+                check_octaves(path, *octaves)?;
+                check_persistence(path, *persistence)
+            }
+            Self::Ridged {
+                source, octaves, ..
+            } => {
+                check_octaves(path, *octaves)?;
+                source.validate(&child("source"))
+            }
+            Self::Billow {
+                source,
+                octaves,
+                persistence,
+            }
+            | Self::Turbulence {
+                source,
+                octaves,
+                persistence,
+            } => {
+                check_octaves(path, *octaves)?;
+                check_persistence(path, *persistence)?;
+                source.validate(&child("source"))
+            }
+            Self::DomainWarp {
+                source,
+                warp,
+                strength,
+            } => {
+                check(
+                    child("strength"),
+                    strength,
+                    strength.is_finite(),
+                    "a finite number",
+                )?;
+                source.validate(&child("source"))?;
+                warp.validate(&child("warp"))
+            }
+            Self::Add { a, b } | Self::Multiply { a, b } => {
+                a.validate(&child("a"))?;
+                b.validate(&child("b"))
+            }
+            Self::Select {
+                control,
+                low,
+                high,
+                falloff,
+                ..
+            } => {
+                check(child("falloff"), falloff, *falloff >= 0.0, "zero or more")?;
+                control.validate(&child("control"))?;
+                low.validate(&child("low"))?;
+                high.validate(&child("high"))
+            }
+            Self::Clamp { source, min, max } => {
+                check(
+                    child("max"),
+                    max,
+                    max >= min,
+                    format!("at least min ({min})"),
+                )?;
+                source.validate(&child("source"))
+            }
+            Self::Curve { source, points } => {
+                check(
+                    child("points"),
+                    format!("{points:?}"),
+                    !points.is_empty() && points.windows(2).all(|pair| pair[0][0] < pair[1][0]),
+                    "at least one point, sorted by strictly increasing input",
+                )?;
+                source.validate(&child("source"))
+            }
+            Self::ScaleBias { source, .. } => source.validate(&child("source")),
+            Self::Constant { .. } => Ok(()),
+        }
+    }
+}
+
+fn check_octaves(path: &str, octaves: usize) -> Result<(), WorldConfigError> {
+    check(
+        format!("{path}.octaves"),
+        octaves,
+        octaves > 0,
+        "at least 1",
+    )
+}
+
+fn check_persistence(path: &str, persistence: f32) -> Result<(), WorldConfigError> {
+    check(
+        format!("{path}.persistence"),
+        persistence,
+        persistence > 0.0 && persistence <= 1.0,
+        "a number in (0, 1]",
+    )
+}
+
+fn check<T: Display>(
+    field: impl Into<String>,
+    value: T,
+    in_range: bool,
+    expected: impl Into<String>,
+) -> Result<(), WorldConfigError> {
+    if in_range {
+        Ok(())
+    } else {
+        Err(WorldConfigError::OutOfRange {
+            field: field.into(),
+            value: value.to_string(),
+            expected: expected.into(),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn resource_errors_name_the_resource_table() {
+        let config: WorldConfig = toml::from_str(
+            r#"
+            [resources.timber]
+            splat_count = 0
+            "#,
+        )
+        .unwrap();
+
+        match config.validate() {
+            Err(WorldConfigError::OutOfRange { field, .. }) => {
+                assert_eq!(field, "resources.timber.splat_count");
+            }
+            other => panic!("expected an out of range field, got {other:?}"),
+        }
+    }
+
+    fn out_of_range_field(toml: &str) -> String {
+        let config: WorldConfig = toml::from_str(toml).unwrap();
+        match config.validate() {
+            Err(WorldConfigError::OutOfRange { field, .. }) => field,
+            other => panic!("expected an out of range field, got {other:?}"),
+        }
+    }
+
+    #[test]
+    fn defaults_and_the_example_world_are_valid() {
+        WorldConfig::default().validate().unwrap();
+
+        let example: WorldConfig = toml::from_str(include_str!("../world.toml")).unwrap();
+        example.validate().unwrap();
+    }
+
+    #[test]
+    fn out_of_range_values_are_rejected() {
+        for (toml, expected) in [
+            ("terrain.resolution = 0", "terrain.resolution"),
+            (
+                "hydraulic_erosion.inertia = 1.5",
+                "hydraulic_erosion.inertia",
+            ),
+            (
+                "hydraulic_erosion.gravity = inf",
+                "hydraulic_erosion.gravity",
+            ),
+            (
+                "hydraulic_erosion.sediment_capacity = nan",
+                "hydraulic_erosion.sediment_capacity",
+            ),
+            (
+                "thermal_erosion.talus_angle = 90.0",
+                "thermal_erosion.talus_angle",
+            ),
+            ("river.width_noise = 1.0", "river.width_noise"),
+            (
+                "chunks.lod_levels = 3\nchunks.resolution = 100",
+                "chunks.lod_levels",
+            ),
+        ] {
+            assert_eq!(out_of_range_field(toml), expected);
+        }
+    }
+
+    #[test]
+    fn graph_errors_name_the_failing_node() {
+        let field = out_of_range_field(
+            r#"
+            [terrain.graph]
+            type = "select"
+            control = { type = "constant", value = 0.0 }
+            low = { type = "constant", value = 0.0 }
+            [terrain.graph.high]
+            type = "ridged"
+            source = { type = "billow", octaves = 0, source = { type = "generator", noise = "perlin" } }
+            "#,
+        );
+        assert_eq!(field, "terrain.graph.high.source.octaves");
+
+        let field = out_of_range_field(
+            r#"
+            [terrain.graph]
+            type = "domain_warp"
+            strength = nan
+            source = { type = "generator", noise = "perlin" }
+            warp = { type = "generator", noise = "perlin" }
+            "#,
+        );
+        assert_eq!(field, "terrain.graph.strength");
+    }
+
+    #[test]
+    fn missing_files_fall_back_to_the_default_world() {
+        let path = std::env::temp_dir().join("megalopolis-missing-world-config.toml");
+        assert!(!path.exists());
+        assert_eq!(
+            WorldConfig::load_or_default(&path).unwrap(),
+            WorldConfig::default()
+        );
+        assert!(matches!(
+            WorldConfig::load(&path),
+            Err(WorldConfigError::Io { .. })
+        ));
+    }
+}
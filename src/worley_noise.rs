@@ -0,0 +1,113 @@
+use nalgebra::Vector2;
+
+use crate::noise::{hash_to_unit, lattice_hash, Noise2D};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum WorleyReturn {
+    /// Distance to the closest feature point
+    F1,
+    /// Distance to the second closest feature point
+    F2,
+    /// Difference between the two, which is zero along the cell borders
+    F2MinusF1,
+}
+
+#[derive(Debug, Clone)]
+/// Cellular noise. Every lattice cell holds one feature point at a hashed position, and the
+/// noise is a distance from the sample to those points, picked by [`WorleyReturn`] and remapped
+/// as `distance * 2 - 1`. F1 is below `sqrt(2)` and mostly below 1, F2 can reach `sqrt(5)`, and
+/// F2MinusF1 lies between zero and F2. Only F1 stays close to `[-1, 1]`, and none of them are
+/// clamped
+pub struct WorleyNoise {
+    seed: u64,
+    return_type: WorleyReturn,
+}
+
+impl WorleyNoise {
+    pub fn new(seed: u64, return_type: WorleyReturn) -> Self {
+        Self { seed, return_type }
+    }
+
+    fn feature_point(&self, x: i32, y: i32) -> Vector2<f32> {
+        let hash = lattice_hash(self.seed, x, y);
+        Vector2::new(
+            x as f32 + hash_to_unit(hash),
+            y as f32 + hash_to_unit(hash.rotate_left(32)),
+        )
+    }
+
+    /// Distances to the closest and second closest feature points
+    pub fn distances(&self, x: f32, y: f32) -> (f32, f32) {
+        let position = Vector2::new(x, y);
+        let cell_x = x.floor() as i32;
+        let cell_y = y.floor() as i32;
+
+        let mut closest = f32::MAX;
+        let mut second_closest = f32::MAX;
+        for offset_y in -1..=1 {
+            for offset_x in -1..=1 {
+                let distance = self
+                    .feature_point(cell_x + offset_x, cell_y + offset_y)
+                    .metric_distance(&position);
+                if distance < closest {
+                    second_closest = closest;
+                    closest = distance;
+                } else if distance < second_closest {
+                    second_closest = distance;
+                }
+            }
+        }
+
+        (closest, second_closest)
+    }
+}
+
+impl Noise2D for WorleyNoise {
+    fn sample(&self, x: f32, y: f32) -> f32 {
+        let (closest, second_closest) = self.distances(x, y);
+        let distance = match self.return_type {
+            WorleyReturn::F1 => closest,
+            WorleyReturn::F2 => second_closest,
+            WorleyReturn::F2MinusF1 => second_closest - closest,
+        };
+        distance * 2.0 - 1.0
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn samples() -> impl Iterator<Item = (f32, f32)> {
+        (0..1600).map(|i| ((i % 40) as f32 * 0.37 - 7.0, (i / 40) as f32 * 0.53 - 9.0))
+    }
+
+    #[test]
+    fn same_seed_gives_the_same_noise() {
+        for return_type in [WorleyReturn::F1, WorleyReturn::F2, WorleyReturn::F2MinusF1] {
+            let first = WorleyNoise::new(9, return_type);
+            let second = WorleyNoise::new(9, return_type);
+            let other = WorleyNoise::new(10, return_type);
+            assert!(samples().all(|(x, y)| first.sample(x, y) == second.sample(x, y)));
+            assert!(samples().any(|(x, y)| first.sample(x, y) != other.sample(x, y)));
+        }
+    }
+
+    #[test]
+    fn distances_stay_within_their_ranges() {
+        let noise = WorleyNoise::new(4, WorleyReturn::F1);
+        let mut largest_f2 = 0.0_f32;
+        for (x, y) in samples() {
+            let (closest, second_closest) = noise.distances(x, y);
+            assert!((0.0..=std::f32::consts::SQRT_2).contains(&closest));
+            assert!(closest <= second_closest && second_closest <= 5.0_f32.sqrt());
+            largest_f2 = largest_f2.max(second_closest);
+
+            let remapped = |return_type| WorleyNoise::new(4, return_type).sample(x, y);
+            assert_eq!(remapped(WorleyReturn::F1), closest * 2.0 - 1.0);
+            assert_eq!(remapped(WorleyReturn::F2), second_closest * 2.0 - 1.0);
+            assert!(remapped(WorleyReturn::F2MinusF1) >= -1.0);
+        }
+        assert!(largest_f2 > 1.0);
+    }
+}